@@ -3,11 +3,9 @@ use std::sync::{Arc, Barrier};
 use std::time::{Duration, Instant};
 use criterion::{BenchmarkId, black_box, Criterion, criterion_group, criterion_main, Throughput};
 use object_pool::Pool;
-use mem_pool::MemPool;
+use mem_pool::{MemPool, ThreadCachedPool};
 
 static KB: usize = 1024;
-static MB: usize = 1024 * KB;
-static GB: usize = 1024 * MB;
 /// The sizes of memory chunks we are going to use for each allocation
 static SIZES: &[usize] = &[
     4 * KB, /*
@@ -30,6 +28,12 @@ static THREAD_COUNT: &[usize] = &[
 enum TestType {
     // Buckets, Capacity per bucket
     MemPool(usize, usize),
+    // Buckets, Capacity per bucket
+    MemPoolAffinity(usize, usize),
+    // Buckets, Capacity per bucket, Buffers left in each bucket
+    DrainedMemPool(usize, usize, usize),
+    // Buckets, Capacity per bucket, Buffers stashed by each thread
+    ThreadCached(usize, usize, usize),
     ObjPool(usize),
     Malloc,
 }
@@ -37,11 +41,12 @@ enum TestType {
 #[derive(Clone)]
 enum Test<T> {
     MemPool(MemPool<T>),
+    ThreadCached(Arc<ThreadCachedPool<T>>),
     ObjPool(Arc<Pool<T>>),
     Malloc,
 }
 
-fn worker_alloc_pool<T>(barrier: Arc<Barrier>, iterations: u64, size: usize, pool: Test<T>) -> Duration {
+fn worker_alloc_pool<T: Send + 'static>(barrier: Arc<Barrier>, iterations: u64, size: usize, pool: Test<T>) -> Duration {
     barrier.wait();
 
     let start_time = Instant::now();
@@ -51,6 +56,9 @@ fn worker_alloc_pool<T>(barrier: Arc<Barrier>, iterations: u64, size: usize, poo
             Test::MemPool(mem) => {
                 let _ = black_box(mem.try_pull());
             }
+            Test::ThreadCached(cached) => {
+                let _ = black_box(cached.try_pull());
+            }
             Test::ObjPool(obj) => {
                 let _ = black_box(obj.try_pull());
             }
@@ -63,13 +71,30 @@ fn worker_alloc_pool<T>(barrier: Arc<Barrier>, iterations: u64, size: usize, poo
     Instant::now().duration_since(start_time)
 }
 
-fn setup_test<T, F>(test: TestType, init_fn: F) -> Test<T> where F: Fn() -> T {
+fn setup_test<T: Send + 'static, F>(test: TestType, init_fn: F) -> Test<T> where F: Fn() -> T {
     match test {
         TestType::MemPool(buckets, cap) => {
             let pool = MemPool::new(buckets, cap, init_fn);
 
             Test::MemPool(pool)
         }
+        TestType::MemPoolAffinity(buckets, cap) => {
+            let pool = MemPool::with_thread_affinity(buckets, cap, init_fn);
+
+            Test::MemPool(pool)
+        }
+        TestType::DrainedMemPool(buckets, cap, left) => {
+            let pool = MemPool::new(buckets, cap, &init_fn);
+
+            pool.resize_each(left, init_fn);
+
+            Test::MemPool(pool)
+        }
+        TestType::ThreadCached(buckets, cap, stash_size) => {
+            let pool = ThreadCachedPool::new(MemPool::new(buckets, cap, init_fn), stash_size);
+
+            Test::ThreadCached(Arc::new(pool))
+        }
         TestType::ObjPool(cap) => {
             let pool = Arc::new(Pool::new(cap, init_fn));
 
@@ -81,7 +106,7 @@ fn setup_test<T, F>(test: TestType, init_fn: F) -> Test<T> where F: Fn() -> T {
     }
 }
 
-fn perform_alloc_test(c: &mut Criterion, name: String, test: TestType) {
+fn perform_alloc_test(c: &mut Criterion, name: &str, test: TestType) {
     let mut alloc_from_pool = c.benchmark_group(name);
 
     for size in SIZES {
@@ -144,12 +169,94 @@ fn perform_alloc_test(c: &mut Criterion, name: String, test: TestType) {
 }
 
 fn basics(c: &mut Criterion) {
-    perform_alloc_test(c, format!("alloc_from_pool"), TestType::MemPool(32, 1000));
+    perform_alloc_test(c, "alloc_from_pool", TestType::MemPool(32, 1000));
+
+    perform_alloc_test(c, "alloc_from_pool_thread_affinity", TestType::MemPoolAffinity(32, 1000));
+
+    perform_alloc_test(c, "alloc_from_drained_pool", TestType::DrainedMemPool(32, 1000, 1));
+
+    perform_alloc_test(c, "alloc_from_thread_cached_pool", TestType::ThreadCached(32, 1000, 2));
+
+    perform_alloc_test(c, "alloc_from_obj_pool", TestType::ObjPool(1000));
+
+    perform_alloc_test(c, "alloc_from_OS", TestType::Malloc);
+}
+
+/// Two threads handing a single buffer back and forth through the blocking pull
+fn blocking_handoff(c: &mut Criterion) {
+    let mut group = c.benchmark_group("blocking_handoff");
+
+    for (name, spin_budget) in [("park", 0), ("spin_then_park", 1000)] {
+        group.bench_function(name, |b| {
+            let pool = MemPool::new(1, 1, || { Vec::<u8>::with_capacity(4 * KB) })
+                .with_spin_budget(spin_budget);
+
+            b.iter_custom(|iterations| {
+                let start_time = Instant::now();
+
+                std::thread::scope(|scope| {
+                    for _ in 0..2 {
+                        scope.spawn(|| {
+                            for _ in 0..iterations / 2 {
+                                let _ = black_box(pool.pull());
+                            }
+                        });
+                    }
+                });
+
+                Instant::now().duration_since(start_time)
+            });
+        });
+    }
+
+    group.finish();
+}
+
+/// Producers and consumers churning through a pool whose outstanding limit is rarely
+/// reached, so most returns have no blocked pull to wake
+fn outstanding_churn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("outstanding_churn");
+
+    for thread_count in [2, 8] {
+        group.bench_with_input(BenchmarkId::from_parameter(thread_count), &thread_count, |b, thread_count| {
+            let pool = MemPool::new(4, 64, || { Vec::<u8>::with_capacity(4 * KB) })
+                .with_max_outstanding(*thread_count);
+
+            b.iter_custom(|iterations| {
+                let start_time = Instant::now();
+
+                std::thread::scope(|scope| {
+                    for _ in 0..*thread_count {
+                        scope.spawn(|| {
+                            for _ in 0..iterations / *thread_count as u64 {
+                                let _ = black_box(pool.pull());
+                            }
+                        });
+                    }
+                });
 
-    perform_alloc_test(c, format!("alloc_from_obj_pool"), TestType::ObjPool(1000));
+                Instant::now().duration_since(start_time)
+            });
+        });
+    }
+
+    group.finish();
+}
+
+/// Single threaded pulls, with a bucket count that can use the mask and one that can't
+fn bucket_index(c: &mut Criterion) {
+    let mut group = c.benchmark_group("bucket_index");
+
+    for bucket_count in [7, 8] {
+        group.bench_with_input(BenchmarkId::from_parameter(bucket_count), &bucket_count, |b, bucket_count| {
+            let pool = MemPool::new(*bucket_count, 16, || { Vec::<u8>::with_capacity(KB) });
+
+            b.iter(|| black_box(pool.try_pull()));
+        });
+    }
 
-    perform_alloc_test(c, format!("alloc_from_OS"), TestType::Malloc);
+    group.finish();
 }
 
-criterion_group!(benches, basics);
+criterion_group!(benches, basics, blocking_handoff, outstanding_churn, bucket_index);
 criterion_main!(benches);
\ No newline at end of file
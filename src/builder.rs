@@ -0,0 +1,128 @@
+use crate::{BucketSelection, InnerPool, MemPool, ResetFn, ValidatorFn};
+
+/// Configures every option of a [MemPool] before creating it, see [MemPool::builder].
+///
+/// By default the pool has a single bucket that holds no buffers, so the bucket
+/// count and capacity are usually set.
+pub struct MemPoolBuilder<T> {
+    bucket_count: usize,
+    capacity_per_bucket: usize,
+    reset: Option<ResetFn<T>>,
+    validator: Option<ValidatorFn<T>>,
+    metrics: bool,
+    affinity: bool,
+    auto_grow: Option<usize>,
+}
+
+impl<T> MemPoolBuilder<T> {
+    pub fn new() -> Self {
+        Self {
+            bucket_count: 1,
+            capacity_per_bucket: 0,
+            reset: None,
+            validator: None,
+            metrics: false,
+            affinity: false,
+            auto_grow: None,
+        }
+    }
+
+    pub fn bucket_count(mut self, bucket_count: usize) -> Self {
+        self.bucket_count = bucket_count;
+
+        self
+    }
+
+    pub fn capacity_per_bucket(mut self, capacity_per_bucket: usize) -> Self {
+        self.capacity_per_bucket = capacity_per_bucket;
+
+        self
+    }
+
+    /// See [MemPool::with_reset]
+    pub fn reset_hook<F>(mut self, reset_fn: F) -> Self where F: Fn(&mut T) + Send + Sync + 'static {
+        self.reset = Some(Box::new(reset_fn));
+
+        self
+    }
+
+    /// See [MemPool::with_validator]
+    pub fn validator<F>(mut self, predicate: F) -> Self where F: Fn(&T) -> bool + Send + Sync + 'static {
+        self.validator = Some(Box::new(predicate));
+
+        self
+    }
+
+    /// See [MemPool::with_metrics]
+    pub fn with_metrics(mut self) -> Self {
+        self.metrics = true;
+
+        self
+    }
+
+    /// Bind each thread to a bucket, see [MemPool::with_thread_affinity]
+    pub fn affinity(mut self, affinity: bool) -> Self {
+        self.affinity = affinity;
+
+        self
+    }
+
+    /// See [MemPool::with_auto_grow]
+    pub fn auto_grow(mut self, max_capacity_per_bucket: usize) -> Self {
+        self.auto_grow = Some(max_capacity_per_bucket);
+
+        self
+    }
+
+    /// Create the pool, filling every bucket with buffers created by `init_fn`
+    pub fn build<F>(self, init_fn: F) -> MemPool<T> where F: Fn() -> T {
+        let mut pool = MemPool::from_inner(InnerPool::new(self.bucket_count, self.capacity_per_bucket, init_fn));
+
+        if self.affinity {
+            pool.selection = BucketSelection::ThreadAffinity;
+        }
+
+        let inner = pool.inner_mut();
+
+        inner.reset = self.reset;
+        inner.validator = self.validator;
+
+        if self.metrics {
+            pool = pool.with_metrics();
+        }
+
+        match self.auto_grow {
+            Some(max_capacity_per_bucket) => pool.with_auto_grow(max_capacity_per_bucket),
+            None => pool,
+        }
+    }
+}
+
+impl<T> Default for MemPoolBuilder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::MemPool;
+
+    #[test]
+    fn builder_applies_options() {
+        let mem_pool = MemPool::builder()
+            .bucket_count(2)
+            .capacity_per_bucket(3)
+            .reset_hook(|mem: &mut Vec<u8>| mem.clear())
+            .with_metrics()
+            .build(|| { Vec::<u8>::with_capacity(4096) });
+
+        assert_eq!(mem_pool.bucket_count(), 2);
+        assert_eq!(mem_pool.capacity(), 6);
+
+        mem_pool.try_pull().unwrap().extend_from_slice(&[1, 2, 3]);
+
+        assert!(mem_pool.drain().all(|mem| mem.is_empty()));
+        assert_eq!(mem_pool.metrics().unwrap().pulls, 1);
+    }
+}
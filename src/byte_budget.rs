@@ -0,0 +1,214 @@
+use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::{InnerPool, PooledMem};
+
+/// The amount of memory retained by a buffer, see [ByteBudgetPool]
+pub trait HasSize {
+    fn byte_size(&self) -> usize;
+}
+
+impl<T> HasSize for Vec<T> {
+    fn byte_size(&self) -> usize {
+        self.capacity() * size_of::<T>()
+    }
+}
+
+impl HasSize for String {
+    fn byte_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl<T> HasSize for Box<[T]> {
+    fn byte_size(&self) -> usize {
+        self.len() * size_of::<T>()
+    }
+}
+
+/// A pool that limits the total size of the buffers it retains, for buffers whose
+/// sizes vary too much for a buffer count to be a useful limit. A returned buffer
+/// that would take the pool over `max_bytes` is dropped instead of being pooled.
+///
+/// Checked out buffers don't count towards the budget.
+pub struct ByteBudgetPool<T> where T: HasSize {
+    inner: InnerPool<T>,
+    counter: AtomicUsize,
+    max_bytes: usize,
+    /// The total [HasSize::byte_size] of the buffers currently stored in the pool
+    bytes: AtomicUsize,
+}
+
+impl<T> ByteBudgetPool<T> where T: HasSize {
+    /// Fill the buckets round robin with buffers created by `init_fn`, until either
+    /// the buckets or the byte budget are full
+    pub fn new<F>(bucket_count: usize, capacity_per_bucket: usize, max_bytes: usize,
+                  init_fn: F) -> Self where F: Fn() -> T {
+        let pool = Self {
+            inner: InnerPool::empty(bucket_count, capacity_per_bucket),
+            counter: AtomicUsize::new(0),
+            max_bytes,
+            bytes: AtomicUsize::new(0),
+        };
+
+        for slot in 0..bucket_count * capacity_per_bucket {
+            if pool.re_attach(slot, pool.inner.created(init_fn())).is_err() {
+                break;
+            }
+        }
+
+        pool
+    }
+
+    pub fn try_pull(&self) -> Option<ByteBudgetMem<'_, T>> {
+        let counter = self.counter.fetch_add(1, Ordering::Relaxed);
+
+        let (bucket, mem) = self.inner.scan_buckets(counter)?;
+
+        self.bytes.fetch_sub(mem.byte_size(), Ordering::Relaxed);
+
+        Some(ByteBudgetMem {
+            pool: self,
+            mem: Some(mem),
+            bucket,
+        })
+    }
+
+    /// Add a buffer to the pool, if it fits in the byte budget and the buckets
+    pub fn attach(&self, mem: T) {
+        let counter = self.counter.fetch_add(1, Ordering::Relaxed);
+
+        let _ = self.re_attach(counter, mem);
+    }
+
+    /// The total size of the buffers currently stored in the pool
+    pub fn bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// The amount of buffers currently stored in the pool
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Store a buffer in the given bucket or the following ones, as long as it fits in
+    /// the byte budget. The buffer is dropped otherwise.
+    fn re_attach(&self, bucket: usize, mut mem: T) -> Result<(), ()> {
+        let size = mem.byte_size();
+
+        self.bytes.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bytes| {
+            bytes.checked_add(size).filter(|total| *total <= self.max_bytes)
+        }).map_err(|_| ())?;
+
+        let start = self.inner.bucket_index(bucket);
+
+        for offset in 0..self.inner.buckets {
+            match self.inner.try_push(self.inner.bucket_index(start + offset), mem) {
+                Ok(()) => return Ok(()),
+                Err(returned) => mem = returned,
+            }
+        }
+
+        // Every bucket is full, so give back the budget that was taken for it
+        self.bytes.fetch_sub(size, Ordering::Relaxed);
+
+        Err(())
+    }
+}
+
+impl<T> Debug for ByteBudgetPool<T> where T: HasSize {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ByteBudgetPool")
+            .field("len", &self.len())
+            .field("bytes", &self.bytes())
+            .field("max_bytes", &self.max_bytes)
+            .finish()
+    }
+}
+
+/// A buffer pulled from a [ByteBudgetPool]
+pub struct ByteBudgetMem<'a, T> where T: HasSize {
+    pool: &'a ByteBudgetPool<T>,
+    mem: Option<T>,
+    bucket: usize,
+}
+
+impl<'a, T> PooledMem<T> for ByteBudgetMem<'a, T> where T: HasSize {
+    fn detach(mut self) -> T {
+        if let Some(mem) = self.mem.take() {
+            mem
+        } else {
+            unreachable!()
+        }
+    }
+}
+
+impl<'a, T> Deref for ByteBudgetMem<'a, T> where T: HasSize {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.mem {
+            Some(mem) => { mem }
+            None => { unreachable!() }
+        }
+    }
+}
+
+impl<'a, T> DerefMut for ByteBudgetMem<'a, T> where T: HasSize {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match &mut self.mem {
+            Some(mem) => { mem }
+            None => { unreachable!() }
+        }
+    }
+}
+
+impl<'a, T> Debug for ByteBudgetMem<'a, T> where T: HasSize + Debug {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T> Drop for ByteBudgetMem<'a, T> where T: HasSize {
+    fn drop(&mut self) {
+        if let Some(mem) = self.mem.take() {
+            let _ = self.pool.re_attach(self.bucket, mem);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ByteBudgetPool;
+
+    const KB: usize = 1024;
+
+    #[test]
+    fn budget_limits_retained_buffers() {
+        let pool = ByteBudgetPool::new(2, 4, 16 * KB, || { Vec::<u8>::with_capacity(4 * KB) });
+
+        assert_eq!(pool.len(), 4);
+        assert_eq!(pool.bytes(), 16 * KB);
+
+        let held = pool.try_pull().unwrap();
+
+        assert_eq!(pool.bytes(), 12 * KB);
+
+        // Takes the place of the pulled buffer, which no longer fits once returned
+        pool.attach(Vec::with_capacity(4 * KB));
+        pool.attach(Vec::with_capacity(4 * KB));
+
+        drop(held);
+
+        assert_eq!(pool.len(), 4);
+        assert_eq!(pool.bytes(), 16 * KB);
+    }
+}
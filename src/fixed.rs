@@ -0,0 +1,182 @@
+use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::AtomicUsize;
+use crossbeam_channel::{Receiver, Sender};
+use crate::{next_counter, PooledMem};
+
+/// A small pool whose bucket count `N` is part of its type. The buckets are stored
+/// inline in an array instead of behind an [std::sync::Arc], so the pool can live on
+/// the stack and handles borrow it directly (the buckets themselves still allocate
+/// their slots).
+///
+/// Pulls use round robin and look at every bucket, like [crate::MemPool::try_pull].
+pub struct FixedMemPool<T, const N: usize> {
+    buckets: [(Sender<T>, Receiver<T>); N],
+    counter: AtomicUsize,
+}
+
+impl<T, const N: usize> FixedMemPool<T, N> {
+    pub fn new<F>(capacity_per_bucket: usize, init_fn: F) -> Self where F: Fn() -> T {
+        const { assert!(N > 0, "A FixedMemPool needs at least one bucket") };
+
+        let buckets = std::array::from_fn(|_| {
+            let (tx, rx) = crossbeam_channel::bounded(capacity_per_bucket);
+
+            for _ in 0..capacity_per_bucket {
+                tx.try_send(init_fn()).unwrap();
+            }
+
+            (tx, rx)
+        });
+
+        Self {
+            buckets,
+            counter: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn try_pull(&self) -> Option<FixedMem<'_, T, N>> {
+        let start = next_counter(&self.counter, N) % N;
+
+        (0..N)
+            .map(|offset| (start + offset) % N)
+            .find_map(|bucket| {
+                self.buckets[bucket].1.try_recv().ok().map(|mem| FixedMem {
+                    pool: self,
+                    mem: Some(mem),
+                    bucket,
+                })
+            })
+    }
+
+    /// The amount of buffers currently stored in the pool
+    pub fn len(&self) -> usize {
+        self.buckets.iter().map(|(_, rx)| rx.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The amount of buffers currently stored in the given bucket (modulo `N`)
+    pub fn bucket_len(&self, bucket: usize) -> usize {
+        self.buckets[bucket % N].1.len()
+    }
+
+    /// Return a buffer to its bucket, or the following ones when it's full. The buffer
+    /// is dropped when the whole pool is at capacity.
+    fn re_attach(&self, bucket: usize, mut mem: T) {
+        for offset in 0..N {
+            match self.buckets[(bucket + offset) % N].0.try_send(mem) {
+                Ok(()) => return,
+                Err(err) => mem = err.into_inner(),
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> Debug for FixedMemPool<T, N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FixedMemPool")
+            .field("buckets", &N)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// A buffer pulled from a [FixedMemPool]
+pub struct FixedMem<'a, T, const N: usize> {
+    pool: &'a FixedMemPool<T, N>,
+    mem: Option<T>,
+    bucket: usize,
+}
+
+impl<'a, T, const N: usize> FixedMem<'a, T, N> {
+    /// The bucket this buffer will be returned to
+    pub fn bucket(&self) -> usize {
+        self.bucket
+    }
+}
+
+impl<'a, T, const N: usize> PooledMem<T> for FixedMem<'a, T, N> {
+    fn detach(mut self) -> T {
+        if let Some(mem) = self.mem.take() {
+            mem
+        } else {
+            unreachable!()
+        }
+    }
+}
+
+impl<'a, T, const N: usize> Deref for FixedMem<'a, T, N> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.mem {
+            Some(mem) => { mem }
+            None => { unreachable!() }
+        }
+    }
+}
+
+impl<'a, T, const N: usize> DerefMut for FixedMem<'a, T, N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match &mut self.mem {
+            Some(mem) => { mem }
+            None => { unreachable!() }
+        }
+    }
+}
+
+impl<'a, T, const N: usize> Debug for FixedMem<'a, T, N> where T: Debug {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T, const N: usize> Drop for FixedMem<'a, T, N> {
+    fn drop(&mut self) {
+        if let Some(mem) = self.mem.take() {
+            self.pool.re_attach(self.bucket, mem);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::{COUNTER_REWIND_AT, FixedMemPool};
+
+    #[test]
+    fn pulls_go_around_every_bucket() {
+        let pool = FixedMemPool::<Vec<u8>, 4>::new(1, || { Vec::with_capacity(16) });
+
+        let held: Vec<_> = (0..4).map(|_| pool.try_pull().unwrap()).collect();
+
+        let mut buckets: Vec<usize> = held.iter().map(|mem| mem.bucket()).collect();
+
+        buckets.sort_unstable();
+
+        assert_eq!(buckets, vec![0, 1, 2, 3]);
+        assert!(pool.try_pull().is_none());
+
+        drop(held);
+
+        assert_eq!(pool.len(), 4);
+        assert!((0..4).all(|bucket| pool.bucket_len(bucket) == 1));
+    }
+
+    #[test]
+    fn round_robin_is_even_across_counter_wrap() {
+        let mut pool = FixedMemPool::<Vec<u8>, 3>::new(1, || { Vec::with_capacity(16) });
+
+        pool.counter = AtomicUsize::new(COUNTER_REWIND_AT - 10);
+
+        let picked: Vec<usize> = (0..30)
+            .map(|_| pool.try_pull().unwrap().bucket())
+            .collect();
+
+        assert!(picked.windows(2).all(|pair| pair[1] == (pair[0] + 1) % 3));
+        assert!(pool.counter.load(Ordering::Relaxed) < 100);
+    }
+}
@@ -1,258 +1,4247 @@
-use std::cell::RefCell;
+use std::borrow::{Borrow, BorrowMut};
+use std::fmt::{Debug, Formatter};
+use std::hash::{BuildHasher, DefaultHasher, Hash, Hasher, RandomState};
 use std::ops::{Deref, DerefMut};
-use std::sync::Arc;
-use crossbeam_channel::{Receiver, Sender};
+use std::sync::{Arc, Weak};
+#[cfg(debug_assertions)]
+use std::backtrace::Backtrace;
+#[cfg(debug_assertions)]
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread::ThreadId;
+use std::time::{Duration, Instant};
+use crossbeam_channel::{Receiver, Select, Sender};
+
+mod builder;
+mod byte_budget;
+mod fixed;
+mod mapped;
+mod size_class;
+mod thread_cached;
+
+pub use builder::MemPoolBuilder;
+pub use byte_budget::{ByteBudgetMem, ByteBudgetPool, HasSize};
+pub use fixed::{FixedMem, FixedMemPool};
+pub use mapped::{MappedMem, MappedPool};
+pub use size_class::{SizeClassMem, SizeClassPool};
+pub use thread_cached::{ThreadCachedMem, ThreadCachedPool};
 
 pub struct MemPool<T> {
     inner: Arc<InnerPool<T>>,
-    counter: RefCell<usize>,
+    counter: AtomicUsize,
+    selection: BucketSelection,
+    /// How many times the blocking pulls retry before parking the thread
+    spin_budget: usize,
+}
+
+/// The shape of a [MemPool], which can be used to build pools with the same
+/// configuration (see [MemPool::config] and [MemPool::from_config]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PoolConfig {
+    pub bucket_count: usize,
+    pub capacity_per_bucket: usize,
+}
+
+/// How a [MemPool] picks the bucket a pull starts from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BucketSelection {
+    /// Use a counter shared by every thread that uses this pool handle
+    RoundRobin,
+    /// Each thread always starts at the same bucket
+    ThreadAffinity,
+    /// Start at the bucket of the CPU the thread is running on
+    #[cfg(feature = "cpu-affinity")]
+    CpuAffinity,
+    /// Start at the bucket holding the most buffers
+    Balanced,
+}
+
+/// The CPU the current thread is running on, if it can be determined
+#[cfg(feature = "cpu-affinity")]
+fn current_cpu() -> Option<usize> {
+    #[cfg(target_os = "linux")]
+    {
+        // SAFETY: sched_getcpu has no preconditions
+        let cpu = unsafe { libc::sched_getcpu() };
+
+        if cpu >= 0 {
+            return Some(cpu as usize);
+        }
+    }
+
+    None
+}
+
+/// Gives each cloned pool handle a different starting bucket, to spread the load
+static CLONE_OFFSETS: AtomicUsize = AtomicUsize::new(1);
+
+static THREAD_INDEXES: AtomicUsize = AtomicUsize::new(0);
+
+/// Round robin counters are moved back by a multiple of the bucket count when they
+/// reach this, so they never wrap around
+const COUNTER_REWIND_AT: usize = 1 << (usize::BITS - 1);
+
+/// Advance a round robin counter over `buckets` buckets, returning its previous value.
+/// When the bucket count isn't a power of two a wrap around would jump back to bucket
+/// 0, so the counter is moved back by a multiple of the bucket count before it gets
+/// there. Only the pull that reaches [COUNTER_REWIND_AT] does it, the others stay a
+/// single `fetch_add`.
+pub(crate) fn next_counter(counter: &AtomicUsize, buckets: usize) -> usize {
+    let previous = counter.fetch_add(1, Ordering::Relaxed);
+
+    if !buckets.is_power_of_two() && previous == COUNTER_REWIND_AT {
+        counter.fetch_sub(counter_rewind(buckets), Ordering::Relaxed);
+    }
+
+    previous
+}
+
+/// The largest multiple of `buckets` up to [COUNTER_REWIND_AT]
+pub(crate) fn counter_rewind(buckets: usize) -> usize {
+    COUNTER_REWIND_AT - COUNTER_REWIND_AT % buckets
+}
+
+thread_local! {
+    static THREAD_INDEX: usize = THREAD_INDEXES.fetch_add(1, Ordering::Relaxed);
 }
 
+/// The buckets shared by every handle of a [MemPool].
+///
+/// Most users should go through [MemPool], which picks the buckets and sets the
+/// options. The low level API of this type (see [InnerPool::try_pull]) lets other
+/// wrappers pull and return buffers with their own bucket selection.
+///
+/// Each bucket is a bounded crossbeam channel. These are lock free, so checking
+/// whether a bucket is empty (or pulling from it) never has to take a mutex and
+/// an empty bucket can be skipped cheaply.
 pub struct InnerPool<T> {
     buckets_txs: Vec<Sender<T>>,
     buckets_rxs: Vec<Receiver<T>>,
     buckets: usize,
-    capacity: usize,
+    /// `buckets - 1` when the bucket count is a power of two, so the bucket
+    /// index can be computed with a mask instead of a modulo
+    mask: Option<usize>,
+    capacities: Vec<usize>,
+    total_capacity: usize,
+    reset: Option<ResetFn<T>>,
+    validator: Option<ValidatorFn<T>>,
+    wipe: Option<fn(&mut T)>,
+    metrics: Option<PoolMetrics>,
+    auto_grow: Option<AutoGrow>,
+    on_exhausted: Option<Box<dyn Fn() + Send + Sync>>,
+    exhausted: AtomicBool,
+    tracer: Option<Box<dyn Fn(TraceEvent) + Send + Sync>>,
+    in_use: AtomicUsize,
+    peak_in_use: AtomicUsize,
+    max_outstanding: Option<OutstandingLimit>,
+    /// Return buffers to the bucket holding the fewest buffers
+    balanced: bool,
+    /// Buffers ever created by the init or fallback functions
+    created_total: AtomicU64,
+    #[cfg(debug_assertions)]
+    leaks: Option<LeakRegistry>,
+    /// Wakes up asynchronous pulls when buffers are returned, or checked in under
+    /// the outstanding limit
+    #[cfg(feature = "async")]
+    returned: tokio::sync::Notify,
+    /// The asynchronous pulls currently waiting, so returns can skip the notify
+    #[cfg(feature = "async")]
+    async_waiters: AtomicUsize,
 }
 
-impl<T> InnerPool<T> {
-    pub fn new<F>(bucket_count: usize, capacity_per_bucket: usize,
-                  init_fn: F) -> Arc<Self> where F: Fn() -> T {
-        let mut buckets_rxs = Vec::with_capacity(bucket_count);
-        let mut buckets_txs = Vec::with_capacity(bucket_count);
+type ResetFn<T> = Box<dyn Fn(&mut T) + Send + Sync>;
 
-        for _ in 0..bucket_count {
-            let (tx, rx) = crossbeam_channel::bounded(capacity_per_bucket);
+type ValidatorFn<T> = Box<dyn Fn(&T) -> bool + Send + Sync>;
 
-            for _ in 0..capacity_per_bucket {
-                tx.try_send(init_fn()).unwrap();
-            }
+type ReturnFn<T> = Box<dyn FnOnce(&T) + Send>;
 
-            buckets_rxs.push(rx);
-            buckets_txs.push(tx);
-        }
+/// What the pool tracks about a checked out buffer, moved along with it between handles
+#[derive(Default)]
+struct Checkout {
+    /// Identifies the buffer for leak detection
+    #[cfg(debug_assertions)]
+    leak_id: Option<u64>,
+    /// When the buffer was pulled, only recorded when metrics are enabled
+    since: Option<Instant>,
+}
 
-        Arc::new(
-            Self {
-                buckets_rxs,
-                buckets_txs,
-                capacity: capacity_per_bucket,
-                buckets: bucket_count,
-            }
-        )
-    }
+/// Where the buffers that are still checked out were pulled from
+#[cfg(debug_assertions)]
+#[derive(Default)]
+struct LeakRegistry {
+    next_id: AtomicU64,
+    outstanding: Mutex<HashMap<u64, Backtrace>>,
+    detached: Mutex<Vec<Backtrace>>,
+}
 
-    fn try_pull_from_bucket<'a>(self: &'a Arc<Self>, counter: usize) -> Option<MutMemShare<'a, T>> {
-        let bucket = counter % self.buckets;
+/// The buffers taken out of a bucket to be looked at (e.g. by [MemPool::inspect_bucket]),
+/// put back in that same bucket when dropped
+struct InspectedBucket<'a, T> {
+    pool: &'a InnerPool<T>,
+    bucket: usize,
+    mems: Vec<T>,
+}
 
-        match self.buckets_rxs[bucket].try_recv() {
-            Ok(mem) => {
-                Some(MutMemShare {
-                    pool: self,
-                    mem: Some(mem),
-                    bucket,
-                })
-            }
-            Err(_) => {
-                None
-            }
+impl<'a, T> Drop for InspectedBucket<'a, T> {
+    fn drop(&mut self) {
+        for mem in self.mems.drain(..) {
+            self.pool.put_back(self.bucket, mem);
         }
     }
+}
 
-    fn try_pull_from_bucket_with_fallback<'a, F>(self: &'a Arc<Self>, counter: usize, fallback: F) -> MutMemShare<'a, T>
-        where F: Fn() -> T {
-        let bucket = counter % self.buckets;
+/// A reservation taken by [InnerPool::reserved], cancelled when dropped
+struct Reservation<'a, T>(&'a InnerPool<T>);
 
-        match self.buckets_rxs[bucket].try_recv() {
-            Ok(mem) => {
-                MutMemShare {
-                    pool: self,
-                    mem: Some(mem),
-                    bucket,
-                }
-            }
-            Err(_) => {
-                MutMemShare {
-                    pool: self,
-                    mem: Some(fallback()),
-                    bucket,
-                }
-            }
+impl<'a, T> Drop for Reservation<'a, T> {
+    fn drop(&mut self) {
+        self.0.unreserve();
+    }
+}
+
+/// A buffer taken out of a bucket by [InnerPool::scan_buckets_filtered] to be looked
+/// at, put back at the end of that same bucket when dropped
+struct LookedAtMem<'a, T> {
+    pool: &'a InnerPool<T>,
+    bucket: usize,
+    mem: Option<T>,
+}
+
+impl<'a, T> Drop for LookedAtMem<'a, T> {
+    fn drop(&mut self) {
+        if let Some(mem) = self.mem.take() {
+            self.pool.put_back(self.bucket, mem);
         }
     }
+}
+
+/// Caps the amount of buffers checked out at the same time
+struct OutstandingLimit {
+    max: usize,
+    /// Lets blocking pulls wait for a buffer to be checked in
+    lock: Mutex<()>,
+    released: Condvar,
+}
+
+/// Lets the buckets grow (up to `max`) when buffers are returned to a full bucket
+struct AutoGrow {
+    max: usize,
+    /// The current capacity of each bucket. The channels themselves are bounded by `max`.
+    limits: Vec<AtomicUsize>,
+}
+
+#[derive(Default)]
+struct PoolMetrics {
+    pulls: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    fallbacks: AtomicU64,
+    reattach_overflow_drops: AtomicU64,
+    held: AtomicU64,
+    hold_time_nanos: AtomicU64,
+}
+
+/// A pull or return of a buffer, reported to the sink set with [MemPool::with_tracing]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// A buffer was pulled from `bucket` by `thread`
+    Pull { bucket: usize, thread: ThreadId, at: Instant },
+    /// A buffer was returned to `bucket` by `thread`. This is the bucket it was pulled
+    /// from, unless it was stolen by a thread with affinity (see [MemPool::try_pull])
+    Return { bucket: usize, thread: ThreadId, at: Instant },
+    /// A buffer pulled from another pool was returned to `bucket` of this one by
+    /// `thread`, see [MutMemShare::reroute]
+    Reroute { bucket: usize, thread: ThreadId, at: Instant },
+}
 
-    fn re_attach(&self, bucket: usize, mem: T) {
-        let final_bucket = bucket % self.buckets;
+/// The values of the metrics of a [MemPool] at a given moment
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolMetricsSnapshot {
+    /// Every attempt to pull a buffer from the pool
+    pub pulls: u64,
+    /// Pulls that found a pooled buffer
+    pub hits: u64,
+    /// Pulls that did not find any buffer
+    pub misses: u64,
+    /// Pulls that had to create a new buffer with the fallback closure
+    pub fallbacks: u64,
+    /// Buffers dropped because the pool was at capacity when they were returned
+    pub reattach_overflow_drops: u64,
+    /// Buffers that were returned (or detached) after being checked out
+    pub held: u64,
+    /// How long those buffers were checked out, in total
+    pub hold_time_nanos: u64,
+}
 
-        let _ = self.buckets_txs[final_bucket].try_send(mem);
+impl PoolMetricsSnapshot {
+    /// How long buffers were checked out on average, if any was returned yet
+    pub fn mean_hold_time(&self) -> Option<Duration> {
+        self.hold_time_nanos.checked_div(self.held).map(Duration::from_nanos)
     }
 }
 
-impl<T> MemPool<T> {
-    pub fn new<F>(bucket_count: usize, capacity_per_bucket: usize,
-                  init_fn: F) -> Self where F: Fn() -> T {
-        let inner_pool = InnerPool::new(bucket_count, capacity_per_bucket, init_fn);
+/// The state of a [MemPool] at a given moment, see [MemPool::snapshot]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PoolSnapshot {
+    /// The amount of buffers stored in each bucket
+    pub bucket_lens: Vec<usize>,
+    /// Buffers currently checked out
+    pub in_use: usize,
+    /// Buffers ever created by the init and fallback functions
+    pub created_total: u64,
+    /// The total amount of buffers the pool can hold
+    pub capacity: usize,
+}
 
-        Self {
-            inner: inner_pool,
-            counter: RefCell::new(0),
+impl PoolMetrics {
+    fn snapshot(&self) -> PoolMetricsSnapshot {
+        PoolMetricsSnapshot {
+            pulls: self.pulls.load(Ordering::Relaxed),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            fallbacks: self.fallbacks.load(Ordering::Relaxed),
+            reattach_overflow_drops: self.reattach_overflow_drops.load(Ordering::Relaxed),
+            held: self.held.load(Ordering::Relaxed),
+            hold_time_nanos: self.hold_time_nanos.load(Ordering::Relaxed),
         }
     }
+}
 
-    pub fn try_pull(& self) -> Option<MutMemShare<'_, T>> {
-        let result = self.inner.try_pull_from_bucket(*self.counter.borrow());
+impl<T> InnerPool<T> {
+    pub fn new<F>(bucket_count: usize, capacity_per_bucket: usize,
+                  init_fn: F) -> Arc<Self> where F: Fn() -> T {
+        Self::new_indexed(bucket_count, capacity_per_bucket, |_, _| init_fn())
+    }
 
-        let mut ref_mut = self.counter.borrow_mut();
+    /// Create a pool where `init_fn` receives the `(bucket, slot)` of the buffer it creates
+    pub fn new_indexed<F>(bucket_count: usize, capacity_per_bucket: usize,
+                          init_fn: F) -> Arc<Self> where F: Fn(usize, usize) -> T {
+        let pool = Self::empty(bucket_count, capacity_per_bucket);
 
-        *ref_mut = ref_mut.wrapping_add(1);
+        pool.fill(init_fn);
 
-        result
+        Arc::new(pool)
     }
 
-    pub fn try_pull_with_fallback<F>(&mut self, fallback: F) -> MutMemShare<'_, T> where F: Fn() -> T {
-        let result = self.inner.try_pull_from_bucket_with_fallback(*self.counter.borrow(), fallback);
+    /// Create a pool with one bucket per entry of `capacities`, each bucket
+    /// holding as many buffers as its entry.
+    pub fn new_with_capacities<F>(capacities: &[usize], init_fn: F) -> Arc<Self> where F: Fn() -> T {
+        let pool = Self::with_capacities(capacities);
+
+        pool.fill(|_, _| init_fn());
 
-        let mut ref_mut = self.counter.borrow_mut();
+        Arc::new(pool)
+    }
 
-        *ref_mut = ref_mut.wrapping_add(1);
+    /// Count a buffer that was just created by an init or fallback function
+    fn created(&self, mem: T) -> T {
+        self.created_total.fetch_add(1, Ordering::Relaxed);
 
-        result
+        mem
     }
-}
 
-impl<T> Clone for MemPool<T> {
-    fn clone(&self) -> Self {
-        Self {
-            inner: self.inner.clone(),
-            counter: RefCell::new(rand::random()),
+    fn fill<F>(&self, init_fn: F) where F: Fn(usize, usize) -> T {
+        for (bucket, tx) in self.buckets_txs.iter().enumerate() {
+            for slot in 0..self.capacities[bucket] {
+                tx.try_send(self.created(init_fn(bucket, slot))).unwrap();
+            }
         }
     }
-}
 
-pub trait PooledMem<T> {
-    fn detach(self) -> T;
-}
+    /// Same as [InnerPool::new], but stops at the first error of `init_fn`, dropping
+    /// the buffers that were already created.
+    pub fn try_new<F, E>(bucket_count: usize, capacity_per_bucket: usize,
+                         init_fn: F) -> Result<Arc<Self>, E> where F: Fn() -> Result<T, E> {
+        let pool = Self::empty(bucket_count, capacity_per_bucket);
 
-pub struct MutMemShare<'a, T> {
-    pool: &'a Arc<InnerPool<T>>,
-    mem: Option<T>,
-    bucket: usize,
-}
+        for tx in &pool.buckets_txs {
+            for _ in 0..capacity_per_bucket {
+                tx.try_send(pool.created(init_fn()?)).unwrap();
+            }
+        }
 
-impl<'a, T> PooledMem<T> for MutMemShare<'a, T> {
-    fn detach(mut self) -> T {
-        if let Some(mem) = self.mem.take() {
-            mem
-        } else {
-            unreachable!()
+        Ok(Arc::new(pool))
+    }
+
+    /// Create a pool from already existing buffers, distributed round robin across
+    /// the buckets. Buffers that don't fit in their bucket are dropped.
+    pub fn from_buffers<I>(bucket_count: usize, capacity_per_bucket: usize,
+                           buffers: I) -> Arc<Self> where I: IntoIterator<Item = T> {
+        let pool = Self::empty(bucket_count, capacity_per_bucket);
+
+        for (index, mem) in buffers.into_iter().enumerate() {
+            let _ = pool.buckets_txs[index % bucket_count].try_send(mem);
         }
+
+        Arc::new(pool)
     }
-}
 
-impl<'a, T> Deref for MutMemShare<'a, T> {
-    type Target = T;
+    fn empty(bucket_count: usize, capacity_per_bucket: usize) -> Self {
+        Self::with_capacities(&vec![capacity_per_bucket; bucket_count])
+    }
 
-    fn deref(&self) -> &Self::Target {
-        match &self.mem {
-            None => { unreachable!() }
-            Some(mem) => { mem }
+    fn with_capacities(capacities: &[usize]) -> Self {
+        let mut buckets_rxs = Vec::with_capacity(capacities.len());
+        let mut buckets_txs = Vec::with_capacity(capacities.len());
+
+        for capacity in capacities {
+            let (tx, rx) = crossbeam_channel::bounded(*capacity);
+
+            buckets_rxs.push(rx);
+            buckets_txs.push(tx);
+        }
+
+        Self {
+            buckets_rxs,
+            buckets_txs,
+            capacities: capacities.to_vec(),
+            total_capacity: capacities.iter().sum(),
+            buckets: capacities.len(),
+            mask: capacities.len().is_power_of_two().then(|| capacities.len() - 1),
+            reset: None,
+            validator: None,
+            wipe: None,
+            metrics: None,
+            auto_grow: None,
+            on_exhausted: None,
+            tracer: None,
+            exhausted: AtomicBool::new(false),
+            in_use: AtomicUsize::new(0),
+            peak_in_use: AtomicUsize::new(0),
+            max_outstanding: None,
+            balanced: false,
+            created_total: AtomicU64::new(0),
+            #[cfg(debug_assertions)]
+            leaks: None,
+            #[cfg(feature = "async")]
+            returned: tokio::sync::Notify::new(),
+            #[cfg(feature = "async")]
+            async_waiters: AtomicUsize::new(0),
         }
     }
-}
 
-impl<'a, T> DerefMut for MutMemShare<'a, T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        match &mut self.mem {
-            None => { unreachable!() }
-            Some(mem) => { mem }
+    fn bucket_index(&self, counter: usize) -> usize {
+        match self.mask {
+            Some(mask) => counter & mask,
+            None => counter % self.buckets,
         }
     }
-}
 
-impl<'a, T> MutMemShare<'a, T> {
-    pub fn freeze(mut self) -> ShareableMem<T> {
-        let pool_clone = Arc::clone(self.pool);
+    /// Advance a round robin counter over the buckets of this pool, see [next_counter]
+    fn next_counter(&self, counter: &AtomicUsize) -> usize {
+        next_counter(counter, self.buckets)
+    }
 
-        ShareableMem {
-            inner: pool_clone,
-            mem: self.mem.take(),
-            bucket: self.bucket,
+    /// The first bucket, starting at the one chosen by the counter, whose length is
+    /// better than every other bucket's according to `better`
+    fn pick_bucket<F>(&self, counter: usize, better: F) -> usize where F: Fn(usize, usize) -> bool {
+        let start = self.bucket_index(counter);
+
+        (1..self.buckets)
+            .map(|offset| self.bucket_index(start + offset))
+            .fold((start, self.buckets_rxs[start].len()), |(best, best_len), bucket| {
+                let len = self.buckets_rxs[bucket].len();
+
+                if better(len, best_len) { (bucket, len) } else { (best, best_len) }
+            })
+            .0
+    }
+
+    /// Try to pull a buffer, starting at the bucket chosen by the counter and
+    /// moving on to the following buckets (wrapping around) when it's empty.
+    fn try_pull_from_bucket<'a>(self: &'a Arc<Self>, counter: usize) -> Option<MutMemShare<'a, T>> {
+        let result = self.reserved(|| self.scan_buckets(counter));
+
+        self.record_pull(result.is_some());
+
+        result.map(|(bucket, mem)| self.share(bucket, mem))
+    }
+
+    fn try_pull_from_exact_bucket<'a>(self: &'a Arc<Self>, bucket: usize) -> Option<MutMemShare<'a, T>> {
+        let bucket = self.bucket_index(bucket);
+
+        let result = self.reserved(|| self.buckets_rxs[bucket].try_recv().ok());
+
+        self.record_pull(result.is_some());
+
+        result.map(|mem| self.share(bucket, mem))
+    }
+
+    /// Wait for a buffer of the given bucket only, until the deadline passes
+    fn pull_from_exact_bucket_blocking<'a>(self: &'a Arc<Self>, bucket: usize,
+                                           deadline: Option<Instant>) -> Option<MutMemShare<'a, T>> {
+        let bucket = self.bucket_index(bucket);
+
+        if !self.reserve_until(deadline) {
+            self.record_pull(false);
+
+            return None;
+        }
+
+        let rx = &self.buckets_rxs[bucket];
+
+        // We hold the senders of every bucket, so an error means the deadline passed
+        let result = match deadline {
+            Some(deadline) => rx.recv_deadline(deadline).ok(),
+            None => rx.recv().ok(),
+        };
+
+        self.record_pull(result.is_some());
+
+        match result {
+            Some(mem) => Some(self.share(bucket, mem)),
+            None => {
+                self.unreserve();
+
+                None
+            }
         }
     }
-}
 
-impl<'a, T> Drop for MutMemShare<'a, T> {
-    fn drop(&mut self) {
-        match self.mem.take() {
-            Some(mem) => {
-                self.pool.re_attach(self.bucket, mem);
+    /// `fallback` receives the bucket the new buffer will be returned to
+    fn try_pull_from_bucket_with_fallback<'a, F>(self: &'a Arc<Self>, counter: usize, fallback: F) -> MutMemShare<'a, T>
+        where F: FnOnce(usize) -> T {
+        // Buffers pulled with a fallback count as outstanding, but are never refused
+        self.reserve_unchecked();
+
+        match self.scan_buckets(counter) {
+            Some((bucket, mem)) => {
+                self.record_pull(true);
+
+                self.share(bucket, mem)
             }
             None => {
-                // Might be a result of a freeze operation
+                if let Some(metrics) = &self.metrics {
+                    metrics.pulls.fetch_add(1, Ordering::Relaxed);
+                    metrics.fallbacks.fetch_add(1, Ordering::Relaxed);
+                }
+
+                let bucket = self.bucket_index(counter);
+
+                self.share(bucket, self.created(fallback(bucket)))
             }
         }
     }
-}
 
-pub struct ShareableMem<T> {
-    inner: Arc<InnerPool<T>>,
-    mem: Option<T>,
-    bucket: usize,
-}
+    /// Pull from any bucket, retrying up to `spin_budget` times before parking the
+    /// thread until a buffer is returned (or the deadline passes).
+    fn pull_from_any_bucket<'a>(self: &'a Arc<Self>, counter: usize, spin_budget: usize,
+                                deadline: Option<Instant>) -> Option<MutMemShare<'a, T>> {
+        if !self.reserve_until(deadline) {
+            self.record_pull(false);
 
-impl<T> PooledMem<T> for ShareableMem<T> {
-    fn detach(mut self) -> T {
-        if let Some(mem) = self.mem.take() {
-            mem
-        } else {
-            unreachable!()
+            return None;
+        }
+
+        for spin in 0..=spin_budget {
+            if let Some((bucket, mem)) = self.scan_buckets(counter) {
+                self.record_pull(true);
+
+                return Some(self.share(bucket, mem));
+            }
+
+            if spin < spin_budget {
+                std::hint::spin_loop();
+            }
         }
+
+        let mut select = Select::new();
+
+        for rx in &self.buckets_rxs {
+            select.recv(rx);
+        }
+
+        let operation = match deadline {
+            Some(deadline) => select.select_deadline(deadline).ok(),
+            None => Some(select.select()),
+        };
+
+        self.record_pull(operation.is_some());
+
+        let Some(operation) = operation else {
+            self.unreserve();
+
+            return None;
+        };
+
+        let bucket = operation.index();
+
+        // We hold the senders of every bucket, so the channel can never be disconnected
+        let mem = operation.recv(&self.buckets_rxs[bucket]).unwrap();
+
+        Some(self.share(bucket, mem))
     }
-}
 
-impl<T> Deref for ShareableMem<T> {
-    type Target = T;
+    /// Take a buffer from the first non empty bucket, starting at the one chosen by the
+    /// counter. This is the only place that finds out every bucket is empty, so it's
+    /// the one calling the exhaustion hook.
+    fn scan_buckets(&self, counter: usize) -> Option<(usize, T)> {
+        let start = self.bucket_index(counter);
 
-    fn deref(&self) -> &Self::Target {
-        match &self.mem {
-            Some(mem) => { mem }
-            None => { unreachable!() }
+        let result = (0..self.buckets)
+            .map(|offset| self.bucket_index(start + offset))
+            .find_map(|bucket| {
+                self.buckets_rxs[bucket].try_recv().ok().map(|mem| (bucket, mem))
+            });
+
+        if result.is_none() {
+            self.record_exhausted();
         }
+
+        result
     }
-}
 
-impl<T> Drop for ShareableMem<T> {
-    fn drop(&mut self) {
-        match self.mem.take() {
-            Some(mem) => {
-                self.inner.re_attach(self.bucket, mem);
+    /// Look through the buckets starting at the one chosen by the counter for a buffer
+    /// matching `pred`. The buffers that were looked at and skipped are put back at
+    /// the end of their bucket.
+    fn scan_buckets_filtered<F>(&self, counter: usize, pred: F) -> Option<(usize, T)>
+        where F: Fn(&T) -> bool {
+        let start = self.bucket_index(counter);
+
+        for offset in 0..self.buckets {
+            let bucket = self.bucket_index(start + offset);
+
+            let rx = &self.buckets_rxs[bucket];
+
+            for _ in 0..rx.len() {
+                let Ok(mem) = rx.try_recv() else { break };
+
+                // Puts the buffer back if it doesn't match, or if `pred` panics
+                let mut looked_at = LookedAtMem {
+                    pool: self,
+                    bucket,
+                    mem: Some(mem),
+                };
+
+                if looked_at.mem.as_ref().is_some_and(&pred) {
+                    return looked_at.mem.take().map(|mem| (bucket, mem));
+                }
             }
-            None => {}
         }
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::MemPool;
+        None
+    }
 
-    #[test]
-    fn assert_simple_functioning() {
-        let mem_pool = MemPool::new(1, 1,
-                                    || { Vec::<u8>::with_capacity(4096) });
+    /// Count a buffer as checked out, unless the outstanding limit was reached
+    fn reserve(&self) -> bool {
+        let in_use = match &self.max_outstanding {
+            Some(limit) => {
+                match self.in_use.fetch_update(Ordering::Relaxed, Ordering::Relaxed,
+                                               |in_use| (in_use < limit.max).then_some(in_use + 1)) {
+                    Ok(in_use) => in_use + 1,
+                    Err(_) => return false,
+                }
+            }
+            None => self.in_use.fetch_add(1, Ordering::Relaxed) + 1,
+        };
 
-        let option = mem_pool.try_pull();
+        self.peak_in_use.fetch_max(in_use, Ordering::Relaxed);
 
-        assert!(option.is_some());
+        true
+    }
 
-        assert!(mem_pool.try_pull().is_none());
+    fn reserve_unchecked(&self) {
+        let in_use = self.in_use.fetch_add(1, Ordering::Relaxed) + 1;
 
-        {
-            let mem = option.unwrap();
+        self.peak_in_use.fetch_max(in_use, Ordering::Relaxed);
+    }
 
-            drop(mem);
+    /// Same as [InnerPool::reserve], but waits until the deadline for a buffer to be
+    /// checked in when the outstanding limit was reached
+    fn reserve_until(&self, deadline: Option<Instant>) -> bool {
+        if self.reserve() {
+            return true;
         }
 
-        assert!(mem_pool.try_pull().is_some());
-    }
-}
\ No newline at end of file
+        let Some(limit) = &self.max_outstanding else { return true };
+
+        let mut guard = limit.lock.lock().unwrap();
+
+        // Checked in buffers notify while holding the lock, so we can't miss a wakeup
+        while !self.reserve() {
+            guard = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+
+                    if now >= deadline {
+                        return false;
+                    }
+
+                    limit.released.wait_timeout(guard, deadline - now).unwrap().0
+                }
+                None => limit.released.wait(guard).unwrap(),
+            };
+        }
+
+        true
+    }
+
+    fn unreserve(&self) {
+        let in_use = self.in_use.fetch_sub(1, Ordering::Relaxed);
+
+        if let Some(limit) = &self.max_outstanding {
+            // Pulls only wait once the limit was reached, so only the check in that
+            // goes back under it has to wake them
+            if in_use < limit.max {
+                return;
+            }
+
+            drop(limit.lock.lock().unwrap());
+
+            limit.released.notify_all();
+
+            #[cfg(feature = "async")]
+            self.notify_returned();
+        }
+    }
+
+    /// Run `pull` if a buffer can be checked out, cancelling the reservation if
+    /// it doesn't find a buffer
+    fn reserved<R, F>(&self, pull: F) -> Option<R> where F: FnOnce() -> Option<R> {
+        if !self.reserve() {
+            return None;
+        }
+
+        // Also cancels the reservation if `pull` panics (e.g. a user predicate)
+        let reservation = Reservation(self);
+
+        let result = pull();
+
+        if result.is_some() {
+            std::mem::forget(reservation);
+        }
+
+        result
+    }
+
+    fn share(self: &Arc<Self>, bucket: usize, mem: T) -> MutMemShare<'_, T> {
+        if let Some(tracer) = &self.tracer {
+            tracer(TraceEvent::Pull { bucket, thread: std::thread::current().id(), at: Instant::now() });
+        }
+
+        MutMemShare {
+            pool: self,
+            mem: Some(mem),
+            bucket,
+            checkout: self.track_checkout(),
+            tag: None,
+            on_return: Mutex::new(None),
+            origin: None,
+        }
+    }
+
+    fn record_pull(&self, hit: bool) {
+        if let Some(metrics) = &self.metrics {
+            metrics.pulls.fetch_add(1, Ordering::Relaxed);
+
+            if hit {
+                metrics.hits.fetch_add(1, Ordering::Relaxed);
+            } else {
+                metrics.misses.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// A pull found every bucket empty. Only the first miss since the pool last
+    /// had a buffer returned to it calls the exhaustion hook.
+    fn record_exhausted(&self) {
+        if let Some(on_exhausted) = &self.on_exhausted {
+            if !self.exhausted.swap(true, Ordering::Relaxed) {
+                on_exhausted();
+            }
+        }
+    }
+
+    fn clear(&self) {
+        for rx in &self.buckets_rxs {
+            rx.try_iter().for_each(drop);
+        }
+    }
+
+    fn resize_each<F>(&self, target: usize, init_fn: F) where F: Fn() -> T {
+        for (bucket, (rx, tx)) in self.buckets_rxs.iter().zip(self.buckets_txs.iter()).enumerate() {
+            let target = target.min(self.bucket_capacity(bucket));
+
+            Self::trim_bucket(rx, target);
+
+            while tx.len() < target {
+                if tx.try_send(self.created(init_fn())).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn trim(&self, keep_per_bucket: usize) {
+        for rx in &self.buckets_rxs {
+            Self::trim_bucket(rx, keep_per_bucket);
+        }
+    }
+
+    fn trim_bucket(rx: &Receiver<T>, keep: usize) {
+        while rx.len() > keep {
+            if rx.try_recv().is_err() {
+                break;
+            }
+        }
+    }
+
+    /// The current capacity of a bucket, which can grow over time with [MemPool::with_auto_grow]
+    fn bucket_capacity(&self, bucket: usize) -> usize {
+        match &self.auto_grow {
+            Some(auto_grow) => auto_grow.limits[bucket].load(Ordering::Relaxed),
+            None => self.capacities[bucket],
+        }
+    }
+
+    /// The amount of buffers currently stored in the given bucket (modulo the bucket count)
+    pub fn bucket_len(&self, bucket: usize) -> usize {
+        self.buckets_rxs[self.bucket_index(bucket)].len()
+    }
+
+    /// The amount of buffers currently stored in the pool, across all buckets
+    pub fn len(&self) -> usize {
+        self.buckets_rxs.iter().map(|rx| rx.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn bucket_count(&self) -> usize {
+        self.buckets
+    }
+
+    /// The total amount of buffers this pool can currently hold, across all buckets
+    pub fn capacity(&self) -> usize {
+        match &self.auto_grow {
+            Some(_) => (0..self.buckets).map(|bucket| self.bucket_capacity(bucket)).sum(),
+            None => self.total_capacity,
+        }
+    }
+
+    /// Try to pull a buffer, starting at bucket `start` (modulo the bucket count) and
+    /// moving on to the following buckets when it's empty. The buffer returns to
+    /// the bucket it was pulled from when the handle is dropped, or the following
+    /// ones when that bucket is full.
+    pub fn try_pull(self: &Arc<Self>, start: usize) -> Option<MutMemShare<'_, T>> {
+        self.try_pull_from_bucket(start)
+    }
+
+    /// Try to pull a buffer from the given bucket (modulo the bucket count) only
+    pub fn try_pull_exact(self: &Arc<Self>, bucket: usize) -> Option<MutMemShare<'_, T>> {
+        self.try_pull_from_exact_bucket(bucket)
+    }
+
+    /// Return a buffer that is not held by a handle (e.g. one that was detached) to
+    /// the given bucket, or the following ones when it's full. The buffer goes
+    /// through the validator and reset hook, like any returned buffer.
+    pub fn attach(&self, bucket: usize, mem: T) {
+        self.track_attach();
+
+        self.re_attach(bucket, mem);
+    }
+
+    /// A checked out buffer is no longer held by a handle
+    fn check_in(&self, checkout: &mut Checkout) {
+        self.unreserve();
+
+        self.record_hold_time(checkout);
+
+        #[cfg(debug_assertions)]
+        if let (Some(leaks), Some(id)) = (&self.leaks, checkout.leak_id.take()) {
+            leaks.outstanding.lock().unwrap().remove(&id);
+        }
+    }
+
+    /// A checked out buffer was detached from its handle. It is still reported as
+    /// leaked until a buffer is attached back to the pool.
+    fn detach_checkout(&self, checkout: &mut Checkout) {
+        self.unreserve();
+
+        self.record_hold_time(checkout);
+
+        #[cfg(debug_assertions)]
+        if let (Some(leaks), Some(id)) = (&self.leaks, checkout.leak_id.take()) {
+            if let Some(backtrace) = leaks.outstanding.lock().unwrap().remove(&id) {
+                leaks.detached.lock().unwrap().push(backtrace);
+            }
+        }
+    }
+
+    fn track_checkout(&self) -> Checkout {
+        #[cfg(debug_assertions)]
+        let leak_id = self.leaks.as_ref().map(|leaks| {
+            let id = leaks.next_id.fetch_add(1, Ordering::Relaxed);
+
+            leaks.outstanding.lock().unwrap().insert(id, Backtrace::force_capture());
+
+            id
+        });
+
+        Checkout {
+            #[cfg(debug_assertions)]
+            leak_id,
+            since: self.metrics.as_ref().map(|_| Instant::now()),
+        }
+    }
+
+    fn record_hold_time(&self, checkout: &mut Checkout) {
+        if let (Some(metrics), Some(since)) = (&self.metrics, checkout.since.take()) {
+            metrics.held.fetch_add(1, Ordering::Relaxed);
+            metrics.hold_time_nanos.fetch_add(since.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn track_attach(&self) {
+        #[cfg(debug_assertions)]
+        if let Some(leaks) = &self.leaks {
+            leaks.detached.lock().unwrap().pop();
+        }
+    }
+
+    /// Return a buffer that was held by a handle to the pool
+    fn release(&self, bucket: usize, mem: T, checkout: &mut Checkout) {
+        self.returned(bucket, checkout);
+
+        self.re_attach(bucket, mem);
+    }
+
+    /// A buffer held by a handle was given back, whichever pool it ends up in
+    fn returned(&self, bucket: usize, checkout: &mut Checkout) {
+        self.check_in(checkout);
+
+        if let Some(tracer) = &self.tracer {
+            tracer(TraceEvent::Return { bucket, thread: std::thread::current().id(), at: Instant::now() });
+        }
+    }
+
+    /// Take in a buffer that was pulled from another pool, see [MutMemShare::reroute]
+    fn rerouted(&self, bucket: usize, mem: T) {
+        if let Some(tracer) = &self.tracer {
+            tracer(TraceEvent::Reroute { bucket, thread: std::thread::current().id(), at: Instant::now() });
+        }
+
+        self.re_attach(bucket, mem);
+    }
+
+    fn try_push(&self, bucket: usize, mem: T) -> Result<(), T> {
+        if let Some(auto_grow) = &self.auto_grow {
+            let limit = auto_grow.limits[bucket].load(Ordering::Relaxed);
+
+            if self.buckets_rxs[bucket].len() >= limit {
+                if limit >= auto_grow.max {
+                    return Err(mem);
+                }
+
+                let grown = (limit * 2).clamp(1, auto_grow.max);
+
+                // If another thread raised the limit in the meantime, that's fine as well
+                let _ = auto_grow.limits[bucket].compare_exchange(limit, grown, Ordering::Relaxed, Ordering::Relaxed);
+            }
+        }
+
+        self.buckets_txs[bucket].try_send(mem).map_err(|err| err.into_inner())
+    }
+
+    fn re_attach(&self, bucket: usize, mut mem: T) {
+        if self.recycle(&mut mem) {
+            self.store(bucket, mem);
+        }
+    }
+
+    /// Get a returned buffer ready to be handed out again. Returns false if the
+    /// validator rejected it, in which case it shouldn't be pooled.
+    fn recycle(&self, mem: &mut T) -> bool {
+        if let Some(validator) = &self.validator {
+            if !validator(mem) {
+                return false;
+            }
+        }
+
+        if let Some(wipe) = self.wipe {
+            wipe(mem);
+        }
+
+        if let Some(reset) = &self.reset {
+            reset(mem);
+        }
+
+        true
+    }
+
+    /// Put a buffer that was only taken out to be looked at back in its bucket. Only
+    /// overflows into the other buckets if returns filled it in the meantime.
+    fn put_back(&self, bucket: usize, mem: T) {
+        if let Err(mem) = self.try_push(bucket, mem) {
+            self.store(bucket, mem);
+        }
+    }
+
+    /// Store a buffer in the given bucket, as is
+    fn store(&self, bucket: usize, mut mem: T) {
+        let bucket = if self.balanced {
+            self.pick_bucket(bucket, |len, best| len < best)
+        } else {
+            bucket
+        };
+
+        let start = self.bucket_index(bucket);
+
+        // When the bucket is full, try the following ones and only drop
+        // the buffer when the whole pool is at capacity
+        for offset in 0..self.buckets {
+            let bucket = self.bucket_index(start + offset);
+
+            match self.try_push(bucket, mem) {
+                Ok(()) => {
+                    if self.on_exhausted.is_some() {
+                        self.exhausted.store(false, Ordering::Relaxed);
+                    }
+
+                    #[cfg(feature = "async")]
+                    self.notify_returned();
+
+                    return;
+                }
+                Err(returned) => mem = returned,
+            }
+        }
+
+        if let Some(metrics) = &self.metrics {
+            metrics.reattach_overflow_drops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> InnerPool<T> {
+    fn notify_returned(&self) {
+        // Pairs with the fence of pull_async: either the pull sees the returned buffer
+        // when it scans the buckets, or we see it waiting
+        std::sync::atomic::fence(Ordering::SeqCst);
+
+        if self.async_waiters.load(Ordering::Relaxed) > 0 {
+            self.returned.notify_waiters();
+        }
+    }
+
+    async fn pull_async(self: &Arc<Self>, counter: usize) -> OwnedMemShare<T> {
+        let _waiter = AsyncWaiter::new(&self.async_waiters);
+
+        loop {
+            let mut notified = std::pin::pin!(self.returned.notified());
+
+            // Register before looking at the buckets, so a buffer returned while we
+            // scan still wakes us up
+            notified.as_mut().enable();
+
+            if let Some((bucket, mem)) = self.reserved(|| self.scan_buckets(counter)) {
+                self.record_pull(true);
+
+                return self.share(bucket, mem).into_owned();
+            }
+
+            notified.await;
+        }
+    }
+}
+
+/// Counts an asynchronous pull as waiting for as long as it's alive, including when
+/// its future is dropped before completing
+#[cfg(feature = "async")]
+struct AsyncWaiter<'a>(&'a AtomicUsize);
+
+#[cfg(feature = "async")]
+impl<'a> AsyncWaiter<'a> {
+    fn new(waiters: &'a AtomicUsize) -> Self {
+        waiters.fetch_add(1, Ordering::Relaxed);
+
+        std::sync::atomic::fence(Ordering::SeqCst);
+
+        Self(waiters)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<'a> Drop for AsyncWaiter<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl<T> InnerPool<T> where T: Send {
+    /// Same as [InnerPool::new], but each bucket is filled by its own thread
+    pub fn new_parallel<F>(bucket_count: usize, capacity_per_bucket: usize,
+                           init_fn: F) -> Arc<Self> where F: Fn() -> T + Sync {
+        let pool = Self::empty(bucket_count, capacity_per_bucket);
+
+        std::thread::scope(|scope| {
+            for tx in &pool.buckets_txs {
+                let init_fn = &init_fn;
+
+                scope.spawn(move || {
+                    for _ in 0..capacity_per_bucket {
+                        tx.try_send(init_fn()).unwrap();
+                    }
+                });
+            }
+        });
+
+        pool.created_total.fetch_add((bucket_count * capacity_per_bucket) as u64, Ordering::Relaxed);
+
+        Arc::new(pool)
+    }
+}
+
+impl<T> MemPool<T> {
+    pub fn new<F>(bucket_count: usize, capacity_per_bucket: usize,
+                  init_fn: F) -> Self where F: Fn() -> T {
+        Self::builder()
+            .bucket_count(bucket_count)
+            .capacity_per_bucket(capacity_per_bucket)
+            .build(init_fn)
+    }
+
+    /// Create a pool with an `init_fn` that can fail (e.g. opening a resource). The
+    /// first error is returned and the buffers that were already created are dropped.
+    pub fn try_new<F, E>(bucket_count: usize, capacity_per_bucket: usize,
+                         init_fn: F) -> Result<Self, E> where F: Fn() -> Result<T, E> {
+        InnerPool::try_new(bucket_count, capacity_per_bucket, init_fn).map(Self::from_inner)
+    }
+
+    /// Configure a pool with a [MemPoolBuilder]
+    pub fn builder() -> MemPoolBuilder<T> {
+        MemPoolBuilder::new()
+    }
+
+    /// Create a pool where `init_fn` receives the `(bucket, slot)` of the buffer it creates
+    pub fn new_indexed<F>(bucket_count: usize, capacity_per_bucket: usize,
+                          init_fn: F) -> Self where F: Fn(usize, usize) -> T {
+        Self::from_inner(InnerPool::new_indexed(bucket_count, capacity_per_bucket, init_fn))
+    }
+
+    /// Create a pool with one bucket per entry of `capacities`, each bucket holding
+    /// as many buffers as its entry. Useful to give more room to busier buckets.
+    pub fn new_with_capacities<F>(capacities: &[usize], init_fn: F) -> Self where F: Fn() -> T {
+        Self::from_inner(InnerPool::new_with_capacities(capacities, init_fn))
+    }
+
+    /// Create a pool from already existing buffers, distributed round robin across
+    /// the buckets. Buffers beyond the capacity of the pool are dropped.
+    pub fn from_buffers<I>(bucket_count: usize, capacity_per_bucket: usize,
+                           buffers: I) -> Self where I: IntoIterator<Item = T> {
+        Self::from_inner(InnerPool::from_buffers(bucket_count, capacity_per_bucket, buffers))
+    }
+
+    /// Create a pool with the shape described by `config`
+    pub fn from_config<F>(config: PoolConfig, init_fn: F) -> Self where F: Fn() -> T {
+        Self::new(config.bucket_count, config.capacity_per_bucket, init_fn)
+    }
+
+    /// Same as [MemPool::new], but the bucket count is rounded up to the next power
+    /// of two, which lets the bucket index be computed with a cheaper mask.
+    pub fn new_pow2<F>(bucket_count: usize, capacity_per_bucket: usize,
+                       init_fn: F) -> Self where F: Fn() -> T {
+        Self::new(bucket_count.next_power_of_two(), capacity_per_bucket, init_fn)
+    }
+
+    /// Create a pool where every buffer is initialized with [Default::default]
+    pub fn new_default(bucket_count: usize, capacity_per_bucket: usize) -> Self where T: Default {
+        Self::new(bucket_count, capacity_per_bucket, T::default)
+    }
+
+    /// Create a pool where the buffers of each bucket are initialized in parallel,
+    /// one thread per bucket. Useful when `init_fn` is expensive.
+    pub fn new_parallel<F>(bucket_count: usize, capacity_per_bucket: usize,
+                           init_fn: F) -> Self where F: Fn() -> T + Sync, T: Send {
+        Self::from_inner(InnerPool::new_parallel(bucket_count, capacity_per_bucket, init_fn))
+    }
+
+    fn from_inner(inner: Arc<InnerPool<T>>) -> Self {
+        Self {
+            inner,
+            counter: AtomicUsize::new(0),
+            selection: BucketSelection::RoundRobin,
+            spin_budget: 0,
+        }
+    }
+
+    /// Create a pool where each thread is bound to a bucket, instead of the
+    /// default round robin selection. This reduces contention when many threads
+    /// share the same pool, as they will tend to stay on their own bucket.
+    pub fn with_thread_affinity<F>(bucket_count: usize, capacity_per_bucket: usize,
+                                   init_fn: F) -> Self where F: Fn() -> T {
+        Self::builder()
+            .bucket_count(bucket_count)
+            .capacity_per_bucket(capacity_per_bucket)
+            .affinity(true)
+            .build(init_fn)
+    }
+
+    /// Pick the starting bucket from the CPU the calling thread is running on, so
+    /// buffers tend to stay warm in that core's cache. Falls back to round robin
+    /// when the CPU can't be determined (currently only supported on Linux).
+    #[cfg(feature = "cpu-affinity")]
+    pub fn with_cpu_affinity(mut self) -> Self {
+        self.selection = BucketSelection::CpuAffinity;
+
+        self
+    }
+
+    /// Pull from the bucket holding the most buffers and return buffers to the bucket
+    /// holding the fewest, which keeps the buckets evenly filled at the cost of
+    /// looking at the length of every bucket. Ties are broken with round robin.
+    ///
+    /// Panics if this pool handle has already been cloned or shared.
+    pub fn with_balanced_selection(mut self) -> Self {
+        self.inner_mut().balanced = true;
+
+        self.selection = BucketSelection::Balanced;
+
+        self
+    }
+
+    /// Make the blocking pulls ([MemPool::pull], [MemPool::pull_timeout]) retry up to
+    /// `spin_budget` times before parking the thread, which reduces latency when
+    /// buffers are returned quickly. Defaults to 0 (park right away).
+    pub fn with_spin_budget(mut self, spin_budget: usize) -> Self {
+        self.spin_budget = spin_budget;
+
+        self
+    }
+
+    /// Run `reset_fn` on every buffer that is returned to the pool, before it
+    /// is made available again (e.g. `|v| v.clear()` for a pool of [Vec]s).
+    ///
+    /// Panics if this pool handle has already been cloned or shared.
+    pub fn with_reset<F>(mut self, reset_fn: F) -> Self where F: Fn(&mut T) + Send + Sync + 'static {
+        self.inner_mut().reset = Some(Box::new(reset_fn));
+
+        self
+    }
+
+    /// Zero every buffer that is returned to the pool, so data such as secrets
+    /// can't be seen by the next user of the buffer.
+    ///
+    /// Panics if this pool handle has already been cloned or shared.
+    #[cfg(feature = "zeroize")]
+    pub fn with_zeroize(mut self) -> Self where T: zeroize::Zeroize {
+        self.inner_mut().wipe = Some(zeroize::Zeroize::zeroize);
+
+        self
+    }
+
+    /// Let buckets grow when buffers are returned to a full bucket, doubling that
+    /// bucket's capacity up to `max_capacity_per_bucket`. Under sustained pressure
+    /// (e.g. buffers created with [MemPool::try_pull_with_fallback]) the pool keeps
+    /// more buffers instead of dropping them.
+    ///
+    /// Panics if this pool handle has already been cloned or shared.
+    pub fn with_auto_grow(mut self, max_capacity_per_bucket: usize) -> Self {
+        let inner = self.inner_mut();
+
+        let max = inner.capacities.iter().copied().fold(max_capacity_per_bucket, usize::max);
+
+        // The channels are bounded, so move the buffers into channels that can hold the maximum
+        for (tx, rx) in inner.buckets_txs.iter_mut().zip(inner.buckets_rxs.iter_mut()) {
+            let (new_tx, new_rx) = crossbeam_channel::bounded(max);
+
+            for mem in rx.try_iter() {
+                new_tx.try_send(mem).unwrap();
+            }
+
+            *tx = new_tx;
+            *rx = new_rx;
+        }
+
+        inner.auto_grow = Some(AutoGrow {
+            max,
+            limits: inner.capacities.iter().map(|cap| AtomicUsize::new(*cap)).collect(),
+        });
+
+        self
+    }
+
+    /// Never let more than `max` buffers be checked out at the same time, even if the
+    /// buckets still hold buffers. Past that, the pulls return `None` and the blocking
+    /// pulls wait for a buffer to be returned or detached.
+    ///
+    /// Buffers pulled with a fallback ([MemPool::try_pull_with_fallback]) always
+    /// succeed, but count towards the limit.
+    ///
+    /// Panics if this pool handle has already been cloned or shared.
+    pub fn with_max_outstanding(mut self, max: usize) -> Self {
+        self.inner_mut().max_outstanding = Some(OutstandingLimit {
+            max,
+            lock: Mutex::new(()),
+            released: Condvar::new(),
+        });
+
+        self
+    }
+
+    /// Call `hook` when a pull finds every bucket empty. To avoid calling it on every
+    /// miss, it is only called again after a buffer has been returned to the pool.
+    ///
+    /// Panics if this pool handle has already been cloned or shared.
+    pub fn with_exhaustion_hook<F>(mut self, hook: F) -> Self where F: Fn() + Send + Sync + 'static {
+        self.inner_mut().on_exhausted = Some(Box::new(hook));
+
+        self
+    }
+
+    /// Report every pull and return of a buffer to `sink`, e.g. to record the pattern of
+    /// a workload and replay it in a benchmark. `sink` runs on the pulling and returning
+    /// threads, so it should be quick.
+    ///
+    /// Panics if this pool handle has already been cloned or shared.
+    pub fn with_tracing<F>(mut self, sink: F) -> Self where F: Fn(TraceEvent) + Send + Sync + 'static {
+        self.inner_mut().tracer = Some(Box::new(sink));
+
+        self
+    }
+
+    /// Keep track of the hits, misses and fallbacks of this pool, see [MemPool::metrics].
+    ///
+    /// Panics if this pool handle has already been cloned or shared.
+    pub fn with_metrics(mut self) -> Self {
+        self.inner_mut().metrics = Some(PoolMetrics::default());
+
+        self
+    }
+
+    /// The current metrics of this pool, if they were enabled with [MemPool::with_metrics]
+    pub fn metrics(&self) -> Option<PoolMetricsSnapshot> {
+        self.inner.metrics.as_ref().map(PoolMetrics::snapshot)
+    }
+
+    /// Only keep returned buffers for which `predicate` returns true, dropping the
+    /// others (e.g. buffers that grew too large or connections that errored).
+    ///
+    /// Panics if this pool handle has already been cloned or shared.
+    pub fn with_validator<F>(mut self, predicate: F) -> Self where F: Fn(&T) -> bool + Send + Sync + 'static {
+        self.inner_mut().validator = Some(Box::new(predicate));
+
+        self
+    }
+
+    /// Record where every buffer is pulled from, so [MemPool::assert_no_leaks] can
+    /// report buffers that were never returned. Only active with `debug_assertions`,
+    /// this does nothing in release builds.
+    ///
+    /// Panics if this pool handle has already been cloned or shared.
+    #[cfg_attr(not(debug_assertions), allow(unused_mut))]
+    pub fn with_leak_detection(mut self) -> Self {
+        #[cfg(debug_assertions)]
+        {
+            self.inner_mut().leaks = Some(LeakRegistry::default());
+        }
+
+        self
+    }
+
+    /// Panic if any buffer pulled since leak detection was enabled is still checked
+    /// out, or was detached and not attached back, listing where they were pulled.
+    /// Does nothing in release builds or without [MemPool::with_leak_detection].
+    pub fn assert_no_leaks(&self) {
+        #[cfg(debug_assertions)]
+        if let Some(leaks) = &self.inner.leaks {
+            let sites: Vec<String> = leaks.outstanding.lock().unwrap().values()
+                .chain(leaks.detached.lock().unwrap().iter())
+                .map(|backtrace| backtrace.to_string())
+                .collect();
+
+            if !sites.is_empty() {
+                panic!("{} pooled buffers were never returned, pulled at:\n{}",
+                       sites.len(), sites.join("\n"));
+            }
+        }
+    }
+
+    /// Convert the buffers with `to` when they are pulled and back with `from` when
+    /// they are returned, e.g. to wrap raw buffers in a typed view. `from` should
+    /// give back the buffer `to` received, so the allocations are reused.
+    pub fn map<U, F, R>(self, to: F, from: R) -> MappedPool<T, U>
+        where F: Fn(T) -> U + Send + Sync + 'static, R: Fn(U) -> T + Send + Sync + 'static {
+        MappedPool::new(self, to, from)
+    }
+
+    /// Whether both handles share the same buckets, e.g. because one is a clone of the other
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    fn inner_mut(&mut self) -> &mut InnerPool<T> {
+        Arc::get_mut(&mut self.inner)
+            .expect("Pool options must be set before the pool is shared")
+    }
+
+    fn next_bucket(&self) -> usize {
+        match self.selection {
+            BucketSelection::RoundRobin => self.inner.next_counter(&self.counter),
+            BucketSelection::ThreadAffinity => THREAD_INDEX.with(|index| *index),
+            #[cfg(feature = "cpu-affinity")]
+            BucketSelection::CpuAffinity => {
+                current_cpu().unwrap_or_else(|| self.inner.next_counter(&self.counter))
+            }
+            BucketSelection::Balanced => {
+                let counter = self.inner.next_counter(&self.counter);
+
+                self.inner.pick_bucket(counter, |len, best| len > best)
+            }
+        }
+    }
+
+    /// Same as [MemPool::try_pull], but the buffer can only be read through the
+    /// returned handle
+    pub fn try_pull_shared(&self) -> Option<SharedMemShare<'_, T>> {
+        self.try_pull().map(|mem| SharedMemShare { mem })
+    }
+
+    /// Set the counter that picks the starting bucket of the next pulls of this handle,
+    /// e.g. to spread clones that drifted into the same buckets again. Only round robin
+    /// (and balanced) selection use the counter.
+    pub fn reseed_counter(&self, seed: usize) {
+        // Keep the seed's bucket, but below the point where the counter is rewound
+        let seed = match self.inner.mask {
+            Some(_) => seed,
+            None => seed % counter_rewind(self.inner.buckets),
+        };
+
+        self.counter.store(seed, Ordering::Relaxed);
+    }
+
+    /// Same as [MemPool::reseed_counter], with a seed taken from the randomly keyed
+    /// std hasher
+    pub fn reseed_counter_random(&self) {
+        self.reseed_counter(RandomState::new().hash_one(Instant::now()) as usize);
+    }
+
+    /// Try to pull a buffer from the pool, starting at the next bucket and looking
+    /// at every other bucket if that one is empty. Each bucket hands out its least
+    /// recently returned buffer first.
+    ///
+    /// With thread (or CPU) affinity, a buffer taken from another bucket is stolen:
+    /// it will be returned to the bucket of the current thread, so its next pulls
+    /// can find it locally.
+    ///
+    /// `None` always means that the pool is empty: the buckets are lock free channels
+    /// whose both ends are owned by the pool, so they can't be poisoned or disconnected.
+    ///
+    /// Pulling and returning a buffer never allocates (unless leak detection is
+    /// enabled, or a reset hook or validator allocates), so this can be used from
+    /// real time threads.
+    pub fn try_pull(&self) -> Option<MutMemShare<'_, T>> {
+        let counter = self.next_bucket();
+
+        let mut mem = self.inner.try_pull_from_bucket(counter)?;
+
+        if !matches!(self.selection, BucketSelection::RoundRobin | BucketSelection::Balanced) {
+            mem.bucket = self.inner.bucket_index(counter);
+        }
+
+        Some(self.pulled_through(mem))
+    }
+
+    /// Try to pull a buffer from the given bucket (modulo the bucket count), without
+    /// looking at any other bucket. The buffer will be returned to that same bucket,
+    /// or the following ones when it's full by then.
+    pub fn try_pull_from(&self, bucket: usize) -> Option<MutMemShare<'_, T>> {
+        self.inner.try_pull_from_exact_bucket(bucket).map(|mem| self.pulled_through(mem))
+    }
+
+    /// Try to pull a buffer from the bucket picked by hashing `key`, ignoring the round
+    /// robin counter, so the same key always targets the same bucket. No other bucket
+    /// is looked at.
+    ///
+    /// The key isn't remembered: the buffer is returned like any other one, to the
+    /// bucket it was pulled from (or whichever bucket the pool's selection picks).
+    pub fn try_pull_for_key<K>(&self, key: &K) -> Option<MutMemShare<'_, T>> where K: Hash + ?Sized {
+        let mut hasher = DefaultHasher::new();
+
+        key.hash(&mut hasher);
+
+        self.inner.try_pull_from_exact_bucket(hasher.finish() as usize).map(|mem| self.pulled_through(mem))
+    }
+
+    /// Pull the first buffer for which `pred` returns true, looking through every bucket
+    /// starting at the next one. Returns `None` if no stored buffer matches.
+    ///
+    /// The buffers that don't match stay in the pool, but are moved to the back of
+    /// their bucket, so this is linear in the amount of stored buffers.
+    pub fn try_pull_filtered<F>(&self, pred: F) -> Option<MutMemShare<'_, T>> where F: Fn(&T) -> bool {
+        let counter = self.next_bucket();
+
+        let result = self.inner.reserved(|| self.inner.scan_buckets_filtered(counter, pred));
+
+        self.inner.record_pull(result.is_some());
+
+        result.map(|(bucket, mem)| self.pulled_through(self.inner.share(bucket, mem)))
+    }
+
+    /// Pull a buffer and run `f` with it, returning the buffer to the pool afterwards
+    /// (even if `f` panics). Returns `None` if no buffer is available.
+    pub fn with_buffer<R, F>(&self, f: F) -> Option<R> where F: FnOnce(&mut T) -> R {
+        let mut mem = self.try_pull()?;
+
+        Some(f(&mut mem))
+    }
+
+    /// Same as [MemPool::try_pull], but also returns the bucket the buffer was pulled from
+    pub fn try_pull_tagged(&self) -> Option<(usize, MutMemShare<'_, T>)> {
+        self.try_pull().map(|mem| (mem.bucket, mem))
+    }
+
+    /// Pull `n` buffers at once. If the pool does not have `n` buffers available,
+    /// the ones that were already pulled are returned and `None` is returned.
+    pub fn try_pull_many(&self, n: usize) -> Option<Vec<MutMemShare<'_, T>>> {
+        let counter = self.next_bucket();
+
+        let mut pulled = Vec::with_capacity(n);
+
+        for index in 0..n {
+            // Dropping the partial set returns every buffer to the pool
+            pulled.push(self.pulled_through(self.inner.try_pull_from_bucket(counter.wrapping_add(index))?));
+        }
+
+        Some(pulled)
+    }
+
+    /// Pull a buffer from the pool, waiting asynchronously until one is available.
+    ///
+    /// The returned handle does not borrow the pool, so it can be held across `.await` points.
+    #[cfg(feature = "async")]
+    pub async fn pull_async(&self) -> OwnedMemShare<T> {
+        let counter = self.next_bucket();
+
+        self.inner.pull_async(counter).await
+    }
+
+    /// Same as [MemPool::try_pull], but the returned handle owns a reference to the
+    /// pool instead of borrowing it.
+    pub fn try_pull_owned(&self) -> Option<OwnedMemShare<T>> {
+        self.try_pull().map(MutMemShare::into_owned)
+    }
+
+    /// Same as [MemPool::try_pull], but the buffer is cleared before being returned.
+    pub fn try_pull_clean(&self) -> Option<MutMemShare<'_, T>> where T: Clearable {
+        let mut mem = self.try_pull()?;
+
+        mem.clear();
+
+        Some(mem)
+    }
+
+    /// Same as [MemPool::try_pull_clean], but the buffer is also grown to hold at least
+    /// `min_capacity` elements, for example to read into it.
+    pub fn try_pull_with_capacity(&self, min_capacity: usize) -> Option<MutMemShare<'_, T>> where T: ReservableBuffer {
+        let mut mem = self.try_pull_clean()?;
+
+        mem.reserve(min_capacity);
+
+        Some(mem)
+    }
+
+    /// Pull a buffer from the pool, blocking the current thread until one becomes available.
+    ///
+    /// If every buffer of the pool has been detached and never attached back, this
+    /// will block forever. Use [MemPool::pull_timeout] to bound the wait.
+    pub fn pull(&self) -> MutMemShare<'_, T> {
+        let counter = self.next_bucket();
+
+        // Without a deadline we can only return once a buffer was received
+        self.pulled_through(self.inner.pull_from_any_bucket(counter, self.spin_budget, None).unwrap())
+    }
+
+    /// Pull a buffer from the pool, waiting at most `dur` for one to become available.
+    pub fn pull_timeout(&self, dur: Duration) -> Option<MutMemShare<'_, T>> {
+        let deadline = Instant::now() + dur;
+
+        let counter = self.next_bucket();
+
+        self.inner.pull_from_any_bucket(counter, self.spin_budget, Some(deadline))
+            .map(|mem| self.pulled_through(mem))
+    }
+
+    /// Pull a buffer from the given bucket (modulo the bucket count) only, blocking the
+    /// current thread until that bucket has a buffer or `timeout` passes. Unlike
+    /// [MemPool::pull], buffers returned to other buckets don't wake the thread.
+    pub fn pull_from_blocking(&self, bucket: usize, timeout: Option<Duration>) -> Option<MutMemShare<'_, T>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        self.inner.pull_from_exact_bucket_blocking(bucket, deadline)
+            .map(|mem| self.pulled_through(mem))
+    }
+
+    pub fn try_pull_with_fallback<F>(&self, fallback: F) -> MutMemShare<'_, T> where F: Fn() -> T {
+        let counter = self.next_bucket();
+
+        self.pulled_through(self.inner.try_pull_from_bucket_with_fallback(counter, |_| fallback()))
+    }
+
+    /// Same as [MemPool::try_pull_with_fallback], but `fallback` receives the
+    /// bucket the newly created buffer belongs to.
+    pub fn try_pull_with_fallback_indexed<F>(&self, fallback: F) -> MutMemShare<'_, T> where F: Fn(usize) -> T {
+        let counter = self.next_bucket();
+
+        self.pulled_through(self.inner.try_pull_from_bucket_with_fallback(counter, fallback))
+    }
+
+    /// Same as [MemPool::try_pull_with_fallback], but also tells whether `fallback`
+    /// had to create the buffer (`true`) or a pooled one was reused (`false`), e.g. to
+    /// count cold allocations without enabling the metrics. `fallback` is only called
+    /// when the pool is empty.
+    pub fn pull_or_else<F>(&self, fallback: F) -> (MutMemShare<'_, T>, bool) where F: FnOnce() -> T {
+        let counter = self.next_bucket();
+
+        let mut created = false;
+
+        let mem = self.inner.try_pull_from_bucket_with_fallback(counter, |_| {
+            created = true;
+
+            fallback()
+        });
+
+        (self.pulled_through(mem), created)
+    }
+
+    /// Remember that `mem` was pulled through this handle, so it can be given back to it
+    fn pulled_through<'a>(&'a self, mut mem: MutMemShare<'a, T>) -> MutMemShare<'a, T> {
+        mem.origin = Some(self);
+
+        mem
+    }
+
+    /// Return a buffer (for example one obtained through [PooledMem::detach]) to the pool.
+    ///
+    /// The bucket is picked with the same round robin counter as the pulls. If that
+    /// bucket is already at capacity, the buffer goes to the following ones, and is
+    /// only dropped when every bucket is full.
+    pub fn attach(&self, mem: T) {
+        let counter = self.next_bucket();
+
+        self.inner.attach(counter, mem);
+    }
+
+    /// Return many buffers to the pool at once, spreading them across the buckets
+    /// starting at the next one. Buffers that don't fit in the pool are dropped.
+    ///
+    /// The buckets are lock free, so this only saves the per buffer bucket selection
+    /// compared to calling [MemPool::attach] in a loop.
+    pub fn attach_many<I>(&self, mems: I) where I: IntoIterator<Item = T> {
+        let counter = self.next_bucket();
+
+        for (index, mem) in mems.into_iter().enumerate() {
+            self.inner.track_attach();
+
+            self.inner.re_attach(counter.wrapping_add(index), mem);
+        }
+    }
+
+    /// Drop every buffer currently stored in the pool, releasing their memory.
+    ///
+    /// Buffers that are checked out are not affected and will still be returned
+    /// to the pool when dropped.
+    pub fn clear(&self) {
+        self.inner.clear()
+    }
+
+    /// Take every buffer currently stored in the pool, one bucket after the other.
+    ///
+    /// Buffers that are checked out are not affected and will still be returned
+    /// to the pool when dropped. Buffers returned while draining may or may not be
+    /// yielded, depending on whether their bucket was already drained.
+    pub fn drain(&self) -> DrainIter<'_, T> {
+        DrainIter {
+            pool: &self.inner,
+            bucket: 0,
+            remaining: self.inner.bucket_len(0),
+        }
+    }
+
+    /// Consume the pool and take every buffer stored in it, e.g. at shutdown.
+    ///
+    /// This only works on the last handle to the pool, once every checked out buffer
+    /// was returned: if clones of this handle or buffer handles are still alive, the
+    /// pool is given back unchanged.
+    pub fn into_buffers(self) -> Result<Vec<T>, MemPool<T>> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(inner) => Ok(inner.buckets_rxs.iter().flat_map(|rx| rx.try_iter()).collect()),
+            Err(inner) => Err(MemPool {
+                inner,
+                counter: self.counter,
+                selection: self.selection,
+                spin_budget: self.spin_budget,
+            }),
+        }
+    }
+
+    /// Move the stored buffers into a new pool with a different shape, keeping their
+    /// allocations. Buffers that don't fit in the new pool are dropped.
+    ///
+    /// Only the bucket selection and spin budget are carried over, options such as
+    /// [MemPool::with_reset] have to be set again on the new pool. Buffers that are
+    /// checked out are still returned to the old pool (which is kept alive by their
+    /// handles) and not to the new one.
+    pub fn rebalance(self, new_bucket_count: usize, new_capacity_per_bucket: usize) -> MemPool<T> {
+        let buffers = self.inner.buckets_rxs.iter().flat_map(|rx| rx.try_iter());
+
+        let mut pool = Self::from_buffers(new_bucket_count, new_capacity_per_bucket, buffers);
+
+        pool.selection = self.selection;
+        pool.spin_budget = self.spin_budget;
+
+        pool
+    }
+
+    /// Drop buffers until each bucket holds at most `keep_per_bucket` of them, e.g.
+    /// to release memory periodically after a burst.
+    ///
+    /// The slots of each bucket are allocated up front, so only the memory of the
+    /// dropped buffers themselves is released.
+    pub fn trim(&self, keep_per_bucket: usize) {
+        self.inner.trim(keep_per_bucket)
+    }
+
+    /// Bring each bucket to `target` buffers, dropping the excess buffers or creating
+    /// new ones with `init_fn`. `target` is clamped to the capacity of the buckets.
+    pub fn resize_each<F>(&self, target: usize, init_fn: F) where F: Fn() -> T {
+        self.inner.resize_each(target, init_fn)
+    }
+
+    /// Create buffers with `init_fn` until every bucket is at capacity again, e.g.
+    /// after a spike drained the pool. The inverse of [MemPool::trim].
+    pub fn refill<F>(&self, init_fn: F) where F: Fn() -> T {
+        // The target is clamped to the capacity of each bucket
+        self.inner.resize_each(usize::MAX, init_fn)
+    }
+
+    /// The amount of buffers currently stored in the pool, across all buckets.
+    ///
+    /// This is only a snapshot, other threads may be pulling or returning
+    /// buffers concurrently so the value can be stale by the time it is read.
+    ///
+    /// It only reads the length of each bucket and never takes a lock, so it is
+    /// cheap enough to be called on hot paths (e.g. for admission control).
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The amount of buffers currently stored in the given bucket (modulo the bucket count)
+    pub fn bucket_len(&self, bucket: usize) -> usize {
+        self.inner.bucket_len(bucket)
+    }
+
+    /// The amount of buffers currently stored in each bucket.
+    ///
+    /// Each bucket is read in turn, so the snapshot is not atomic across buckets.
+    pub fn bucket_lens(&self) -> Vec<usize> {
+        self.inner.buckets_rxs.iter().map(|rx| rx.len()).collect()
+    }
+
+    /// The amount of buffers currently checked out of the pool by handles
+    /// (including buffers created by a fallback closure)
+    pub fn in_use(&self) -> usize {
+        self.inner.in_use.load(Ordering::Relaxed)
+    }
+
+    /// Run `f` with the buffers currently stored in the given bucket (modulo the bucket
+    /// count), in the order they will be pulled. Meant for tests and debugging.
+    ///
+    /// The buffers are taken out of the bucket while `f` runs, so concurrent pulls
+    /// won't see them. They are put back in the same bucket afterwards, even if `f`
+    /// panics.
+    pub fn inspect_bucket<R, F>(&self, bucket: usize, f: F) -> R where F: FnOnce(&[T]) -> R {
+        let bucket = self.inner.bucket_index(bucket);
+
+        let rx = &self.inner.buckets_rxs[bucket];
+
+        let inspected = InspectedBucket {
+            pool: &self.inner,
+            bucket,
+            mems: (0..rx.len()).map_while(|_| rx.try_recv().ok()).collect(),
+        };
+
+        f(&inspected.mems)
+    }
+
+    /// The maximum amount of buffers that have been checked out at the same time
+    pub fn peak_in_use(&self) -> usize {
+        self.inner.peak_in_use.load(Ordering::Relaxed)
+    }
+
+    /// Capture the bucket lengths and counters of this pool in one go, e.g. for a
+    /// dashboard.
+    ///
+    /// The buckets are lock free, so there is nothing to lock to freeze the pool: under
+    /// concurrent use the values are read one after the other and may be slightly
+    /// inconsistent with each other.
+    pub fn snapshot(&self) -> PoolSnapshot {
+        PoolSnapshot {
+            bucket_lens: self.bucket_lens(),
+            in_use: self.in_use(),
+            created_total: self.created_total(),
+            capacity: self.capacity(),
+        }
+    }
+
+    /// The amount of buffers ever created by the init and fallback functions of this
+    /// pool. Unlike [MemPool::len], this is not affected by clearing or trimming.
+    pub fn created_total(&self) -> u64 {
+        self.inner.created_total.load(Ordering::Relaxed)
+    }
+
+    /// The total amount of buffers this pool can hold, across all buckets
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    pub fn bucket_count(&self) -> usize {
+        self.inner.buckets
+    }
+
+    /// The capacity of each bucket. For pools created with [MemPool::new_with_capacities]
+    /// this is the capacity of the largest bucket, see [MemPool::bucket_capacity].
+    pub fn capacity_per_bucket(&self) -> usize {
+        (0..self.inner.buckets).map(|bucket| self.inner.bucket_capacity(bucket)).max().unwrap_or(0)
+    }
+
+    /// The shape of this pool. The buffers themselves are not part of it.
+    pub fn config(&self) -> PoolConfig {
+        PoolConfig {
+            bucket_count: self.bucket_count(),
+            capacity_per_bucket: self.capacity_per_bucket(),
+        }
+    }
+
+    /// The capacity of the given bucket (modulo the bucket count)
+    pub fn bucket_capacity(&self, bucket: usize) -> usize {
+        self.inner.bucket_capacity(self.inner.bucket_index(bucket))
+    }
+}
+
+#[cfg(feature = "prefault")]
+impl MemPool<Vec<u8>> {
+    /// Write to every page of the unused capacity of the stored buffers, so the
+    /// memory is actually resident and the first use of a buffer doesn't page fault.
+    ///
+    /// Meant to warm the pool up before it is used: the buffers of each bucket are
+    /// taken out while they are touched, so concurrent pulls would miss them (and
+    /// could call the exhaustion hook). They are put back in the same bucket.
+    pub fn prefault(&self) {
+        // The smallest page size in common use, touching more often than needed is harmless
+        const PAGE_SIZE: usize = 4096;
+
+        for (bucket, rx) in self.inner.buckets_rxs.iter().enumerate() {
+            let mut touched = InspectedBucket {
+                pool: &self.inner,
+                bucket,
+                mems: (0..rx.len()).map_while(|_| rx.try_recv().ok()).collect(),
+            };
+
+            for mem in &mut touched.mems {
+                let spare = mem.spare_capacity_mut();
+
+                for offset in (0..spare.len()).step_by(PAGE_SIZE) {
+                    // SAFETY: offset is within the spare capacity, and writing to
+                    // uninitialized bytes is fine. The write is volatile so it can't be
+                    // optimized out, as nothing ever reads it.
+                    unsafe {
+                        spare.as_mut_ptr().add(offset).cast::<u8>().write_volatile(0);
+                    }
+                }
+            }
+        }
+    }}
+
+impl MemPool<Box<[u8]>> {
+    /// Create a pool of fixed size, zeroed byte buffers of `buffer_len` bytes.
+    /// Returned buffers are pooled as is, so the allocations are reused.
+    pub fn new_byte_pool(bucket_count: usize, capacity_per_bucket: usize, buffer_len: usize) -> Self {
+        Self::new(bucket_count, capacity_per_bucket, || vec![0u8; buffer_len].into_boxed_slice())
+    }
+}
+
+/// Create a pool with a single bucket holding the buffers of the vec. Fails, giving
+/// the vec back, if it is empty.
+impl<T> TryFrom<Vec<T>> for MemPool<T> {
+    type Error = Vec<T>;
+
+    fn try_from(buffers: Vec<T>) -> Result<Self, Self::Error> {
+        if buffers.is_empty() {
+            return Err(buffers);
+        }
+
+        Ok(Self::from_buffers(1, buffers.len(), buffers))
+    }
+}
+
+impl<T> Clone for MemPool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            counter: AtomicUsize::new(CLONE_OFFSETS.fetch_add(1, Ordering::Relaxed)),
+            selection: self.selection,
+            spin_budget: self.spin_budget,
+        }
+    }
+}
+
+impl<T> Debug for MemPool<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemPool")
+            .field("buckets", &self.bucket_count())
+            .field("capacity_per_bucket", &self.capacity_per_bucket())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Buffers that can be emptied before being handed out by [MemPool::try_pull_clean]
+pub trait Clearable {
+    fn clear(&mut self);
+}
+
+impl<T> Clearable for Vec<T> {
+    fn clear(&mut self) {
+        Vec::clear(self)
+    }
+}
+
+impl Clearable for String {
+    fn clear(&mut self) {
+        String::clear(self)
+    }
+}
+
+/// Buffers that can grow to a requested capacity, for [MemPool::try_pull_with_capacity]
+pub trait ReservableBuffer: Clearable {
+    /// Make room for at least `additional` more elements
+    fn reserve(&mut self, additional: usize);
+}
+
+impl<T> ReservableBuffer for Vec<T> {
+    fn reserve(&mut self, additional: usize) {
+        Vec::reserve(self, additional)
+    }
+}
+
+impl ReservableBuffer for String {
+    fn reserve(&mut self, additional: usize) {
+        String::reserve(self, additional)
+    }
+}
+
+/// Iterator over the buffers taken out of a pool by [MemPool::drain]
+pub struct DrainIter<'a, T> {
+    pool: &'a InnerPool<T>,
+    bucket: usize,
+    /// How many buffers we still take from the current bucket, so buffers that
+    /// keep being returned to it can't keep us there forever
+    remaining: usize,
+}
+
+impl<'a, T> Iterator for DrainIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.bucket < self.pool.buckets {
+            if self.remaining > 0 {
+                if let Ok(mem) = self.pool.buckets_rxs[self.bucket].try_recv() {
+                    self.remaining -= 1;
+
+                    return Some(mem);
+                }
+            }
+
+            self.bucket += 1;
+
+            if self.bucket < self.pool.buckets {
+                self.remaining = self.pool.bucket_len(self.bucket);
+            }
+        }
+
+        None
+    }
+}
+
+pub trait PooledMem<T> {
+    fn detach(self) -> T;
+}
+
+pub struct MutMemShare<'a, T> {
+    pool: &'a Arc<InnerPool<T>>,
+    mem: Option<T>,
+    bucket: usize,
+    checkout: Checkout,
+    /// Set by the user for correlation, see [MutMemShare::set_tag]
+    tag: Option<u64>,
+    /// Only accessed through `&mut self`, the mutex keeps the handle `Sync`
+    on_return: Mutex<Option<ReturnFn<T>>>,
+    /// The handle the buffer was pulled through, if it wasn't pulled from the
+    /// [InnerPool] directly
+    origin: Option<&'a MemPool<T>>,
+}
+
+impl<'a, T> PooledMem<T> for MutMemShare<'a, T> {
+    fn detach(mut self) -> T {
+        if let Some(mem) = self.mem.take() {
+            self.pool.detach_checkout(&mut self.checkout);
+
+            mem
+        } else {
+            unreachable!()
+        }
+    }
+}
+
+/// A handle always holds its buffer until it is consumed (by `detach`, `freeze`...),
+/// so dereferencing can't fail. [MutMemShare::get] exposes the buffer without
+/// relying on that invariant.
+impl<'a, T> Deref for MutMemShare<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.mem {
+            None => { unreachable!() }
+            Some(mem) => { mem }
+        }
+    }
+}
+
+impl<'a, T> DerefMut for MutMemShare<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match &mut self.mem {
+            None => { unreachable!() }
+            Some(mem) => { mem }
+        }
+    }
+}
+
+/// ```
+/// use mem_pool::MemPool;
+///
+/// fn take(bytes: impl AsRef<[u8]>) -> usize {
+///     bytes.as_ref().len()
+/// }
+///
+/// let pool = MemPool::new(1, 1, || vec![0u8; 16]);
+/// let mem = pool.try_pull().unwrap();
+///
+/// assert_eq!(take(mem.as_ref()), 16);
+/// ```
+impl<'a, T> AsRef<T> for MutMemShare<'a, T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<'a, T> AsMut<T> for MutMemShare<'a, T> {
+    fn as_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+impl<'a, T> Borrow<T> for MutMemShare<'a, T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<'a, T> BorrowMut<T> for MutMemShare<'a, T> {
+    fn borrow_mut(&mut self) -> &mut T {
+        self
+    }
+}
+
+/// ```
+/// use mem_pool::MemPool;
+///
+/// let pool = MemPool::new(1, 1, || vec![1u8, 2, 3]);
+/// let mut mem = pool.try_pull().unwrap();
+///
+/// for byte in &mut mem {
+///     *byte *= 2;
+/// }
+///
+/// assert_eq!((&mem).into_iter().sum::<u8>(), 12);
+/// ```
+impl<'a, 'b, T> IntoIterator for &'a MutMemShare<'b, Vec<T>> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, 'b, T> IntoIterator for &'a mut MutMemShare<'b, Vec<T>> {
+    type Item = &'a mut T;
+    type IntoIter = std::slice::IterMut<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<'a, T> Debug for MutMemShare<'a, T> where T: Debug {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T> MutMemShare<'a, T> {
+    /// The buffer held by this handle, without panicking if it is missing
+    pub fn get(&self) -> Option<&T> {
+        self.mem.as_ref()
+    }
+
+    /// The buffer held by this handle, without panicking if it is missing
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        self.mem.as_mut()
+    }
+
+    /// Attach a tag (e.g. a span id) to this checkout, for observability. Tags don't
+    /// affect pooling and every pull starts without one.
+    pub fn set_tag(&mut self, tag: u64) {
+        self.tag = Some(tag);
+    }
+
+    /// The tag set with [MutMemShare::set_tag], if any
+    pub fn tag(&self) -> Option<u64> {
+        self.tag
+    }
+
+    /// Run `f` with the buffer when this handle returns it to the pool, before the
+    /// pool's reset hook. Unlike [MemPool::with_reset], this only applies to this
+    /// checkout, and it is discarded if the buffer leaves the handle in any other way
+    /// (detached, frozen...).
+    pub fn on_return<F>(mut self, f: F) -> Self where F: FnOnce(&T) + Send + 'static {
+        *self.on_return.get_mut().unwrap() = Some(Box::new(f));
+
+        self
+    }
+
+    /// Return the buffer to `dest` instead of the pool it came from, e.g. to pass
+    /// buffers along a pipeline of pools. The original pool no longer counts it as
+    /// checked out.
+    ///
+    /// The original pool sees this as a return (including its tracing and the
+    /// [MutMemShare::on_return] callback), while `dest` reports a
+    /// [TraceEvent::Reroute] and runs its own reset hook and validator.
+    pub fn reroute(mut self, dest: &MemPool<T>) {
+        if let Some(mem) = self.mem.take() {
+            if let Some(on_return) = self.on_return.get_mut().unwrap().take() {
+                on_return(&mem);
+            }
+
+            self.pool.returned(self.bucket, &mut self.checkout);
+
+            dest.inner.rerouted(dest.next_bucket(), mem);
+        }
+    }
+
+    /// Detach the buffer from the pool, also returning a handle to the pool it came
+    /// from so it can later be given back with [MemPool::attach]. The handle is a clone
+    /// of the one the buffer was pulled through, so it keeps its bucket selection and
+    /// spin budget.
+    pub fn detach_with_pool(self) -> (MemPool<T>, T) {
+        let pool = match self.origin {
+            Some(origin) => origin.clone(),
+            None => MemPool::from_inner(Arc::clone(self.pool)),
+        };
+
+        (pool, self.detach())
+    }
+
+    /// Turn this into a [ShareableMem], which can be cloned and shared for read
+    /// only access. The buffer returns to the pool when the last clone is dropped.
+    ///
+    /// Returns `None` if this handle no longer holds its buffer, instead of creating
+    /// a [ShareableMem] that would panic when dereferenced. Handles always hold their
+    /// buffer until they are consumed, so this doesn't happen through the public API.
+    ///
+    /// Use [MutMemShare::freeze_mut] instead to keep exclusive, mutable access.
+    pub fn freeze(mut self) -> Option<ShareableMem<T>> {
+        let mem = self.mem.take()?;
+
+        Some(ShareableMem {
+            frozen: Arc::new(FrozenMem {
+                inner: Arc::clone(self.pool),
+                mem: Some(mem),
+                bucket: self.bucket,
+                checkout: std::mem::take(&mut self.checkout),
+            }),
+        })
+    }
+
+    /// Turn this into an [OwnedMemShare], which no longer borrows the pool so it can
+    /// be moved to other threads, but stays exclusive: it can't be cloned.
+    ///
+    /// ```compile_fail
+    /// use mem_pool::{MemPool, OwnedMemShare};
+    ///
+    /// let pool = MemPool::new(1, 1, || { Vec::<u8>::new() });
+    ///
+    /// let exclusive = pool.try_pull().unwrap().freeze_mut();
+    ///
+    /// let _copy: OwnedMemShare<Vec<u8>> = exclusive.clone();
+    /// ```
+    pub fn freeze_mut(self) -> OwnedMemShare<T> {
+        self.into_owned()
+    }
+
+    fn into_owned(mut self) -> OwnedMemShare<T> {
+        OwnedMemShare {
+            pool: Arc::clone(self.pool),
+            mem: self.mem.take(),
+            bucket: self.bucket,
+            checkout: std::mem::take(&mut self.checkout),
+        }
+    }
+}
+
+impl<'a, T> Drop for MutMemShare<'a, T> {
+    fn drop(&mut self) {
+        match self.mem.take() {
+            Some(mem) => {
+                if let Some(on_return) = self.on_return.get_mut().unwrap().take() {
+                    on_return(&mem);
+                }
+
+                self.pool.release(self.bucket, mem, &mut self.checkout);
+            }
+            None => {
+                // Might be a result of a freeze operation
+            }
+        }
+    }
+}
+
+/// A pooled buffer that can only be read, see [MemPool::try_pull_shared]. It is
+/// returned to the pool on drop, like a [MutMemShare].
+///
+/// ```compile_fail
+/// use mem_pool::MemPool;
+///
+/// let pool = MemPool::new(1, 1, || { Vec::<u8>::new() });
+///
+/// let mut shared = pool.try_pull_shared().unwrap();
+///
+/// let _mem: &mut Vec<u8> = &mut *shared;
+/// ```
+pub struct SharedMemShare<'a, T> {
+    mem: MutMemShare<'a, T>,
+}
+
+impl<'a, T> PooledMem<T> for SharedMemShare<'a, T> {
+    fn detach(self) -> T {
+        self.mem.detach()
+    }
+}
+
+impl<'a, T> Deref for SharedMemShare<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.mem
+    }
+}
+
+impl<'a, T> AsRef<T> for SharedMemShare<'a, T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<'a, T> Borrow<T> for SharedMemShare<'a, T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<'a, T> Debug for SharedMemShare<'a, T> where T: Debug {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+/// A mutable pooled buffer that does not borrow the pool it came from, so it
+/// can be moved to other threads or stored in `'static` contexts.
+pub struct OwnedMemShare<T> {
+    pool: Arc<InnerPool<T>>,
+    mem: Option<T>,
+    bucket: usize,
+    checkout: Checkout,
+}
+
+impl<T> PooledMem<T> for OwnedMemShare<T> {
+    fn detach(mut self) -> T {
+        if let Some(mem) = self.mem.take() {
+            self.pool.detach_checkout(&mut self.checkout);
+
+            mem
+        } else {
+            unreachable!()
+        }
+    }
+}
+
+impl<T> Deref for OwnedMemShare<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.mem {
+            Some(mem) => { mem }
+            None => { unreachable!() }
+        }
+    }
+}
+
+impl<T> DerefMut for OwnedMemShare<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match &mut self.mem {
+            Some(mem) => { mem }
+            None => { unreachable!() }
+        }
+    }
+}
+
+impl<T> Debug for OwnedMemShare<T> where T: Debug {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for OwnedMemShare<T> {
+    fn drop(&mut self) {
+        if let Some(mem) = self.mem.take() {
+            self.pool.release(self.bucket, mem, &mut self.checkout);
+        }
+    }
+}
+
+/// A frozen buffer, shared by every clone of a [ShareableMem]
+struct FrozenMem<T> {
+    inner: Arc<InnerPool<T>>,
+    mem: Option<T>,
+    bucket: usize,
+    checkout: Checkout,
+}
+
+impl<T> Drop for FrozenMem<T> {
+    fn drop(&mut self) {
+        if let Some(mem) = self.mem.take() {
+            self.inner.release(self.bucket, mem, &mut self.checkout);
+        }
+    }
+}
+
+/// A read only pooled buffer which can be cloned and shared between threads.
+/// The buffer is returned to the pool once every clone has been dropped.
+pub struct ShareableMem<T> {
+    frozen: Arc<FrozenMem<T>>,
+}
+
+impl<T> ShareableMem<T> {
+    /// The bucket this buffer will be returned to
+    pub fn bucket(&self) -> usize {
+        self.frozen.bucket
+    }
+
+    /// Mutable access to the buffer, only available while this is the only clone
+    pub fn get_mut(&mut self) -> Option<&mut T> {
+        Arc::get_mut(&mut self.frozen).and_then(|frozen| frozen.mem.as_mut())
+    }
+
+    /// Turn this back into a mutable handle, which keeps returning the buffer to
+    /// the bucket it was originally pulled from. Fails if other clones are still alive.
+    pub fn thaw(self) -> Result<OwnedMemShare<T>, Self> {
+        match Arc::try_unwrap(self.frozen) {
+            Ok(mut frozen) => {
+                Ok(OwnedMemShare {
+                    pool: Arc::clone(&frozen.inner),
+                    mem: frozen.mem.take(),
+                    bucket: frozen.bucket,
+                    checkout: std::mem::take(&mut frozen.checkout),
+                })
+            }
+            Err(frozen) => Err(Self { frozen }),
+        }
+    }
+
+    /// Create a [WeakMem] to this buffer, which does not keep it from returning to the pool
+    pub fn downgrade(&self) -> WeakMem<T> {
+        WeakMem {
+            frozen: Arc::downgrade(&self.frozen),
+        }
+    }
+}
+
+/// A weak reference to a [ShareableMem], see [ShareableMem::downgrade]
+pub struct WeakMem<T> {
+    frozen: Weak<FrozenMem<T>>,
+}
+
+impl<T> WeakMem<T> {
+    /// Get a [ShareableMem] back, unless the buffer was already returned to the pool
+    pub fn upgrade(&self) -> Option<ShareableMem<T>> {
+        self.frozen.upgrade().map(|frozen| ShareableMem { frozen })
+    }
+}
+
+impl<T> Clone for WeakMem<T> {
+    fn clone(&self) -> Self {
+        Self {
+            frozen: Weak::clone(&self.frozen),
+        }
+    }
+}
+
+impl<T> Clone for ShareableMem<T> {
+    fn clone(&self) -> Self {
+        Self {
+            frozen: Arc::clone(&self.frozen),
+        }
+    }
+}
+
+impl<T> Deref for ShareableMem<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.frozen.mem {
+            Some(mem) => { mem }
+            None => { unreachable!() }
+        }
+    }
+}
+
+impl<T> Debug for ShareableMem<T> where T: Debug {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use crate::{COUNTER_REWIND_AT, InnerPool, MemPool, PoolMetricsSnapshot, PoolSnapshot, PooledMem, TraceEvent};
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn assert_simple_functioning() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let option = mem_pool.try_pull();
+
+        assert!(option.is_some());
+
+        assert!(mem_pool.try_pull().is_none());
+
+        {
+            let mem = option.unwrap();
+
+            drop(mem);
+        }
+
+        assert!(mem_pool.try_pull().is_some());
+    }
+
+    #[test]
+    fn frozen_mem_returns_to_pool() {
+        let mem_pool = MemPool::new(1, 4,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let mut frozen = Vec::new();
+
+        while let Some(mem) = mem_pool.try_pull() {
+            frozen.push(mem.freeze().unwrap());
+        }
+
+        assert_eq!(frozen.len(), 4);
+        assert!(mem_pool.try_pull().is_none());
+
+        drop(frozen);
+
+        assert!(mem_pool.try_pull().is_some());
+    }
+
+    #[test]
+    fn frozen_mem_is_usable() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let mut frozen = mem_pool.try_pull().unwrap().freeze().unwrap();
+
+        frozen.get_mut().unwrap().extend_from_slice(&[1, 2, 3]);
+
+        assert_eq!(&frozen[..], &[1, 2, 3]);
+
+        let mut clone = frozen.clone();
+
+        assert!(clone.get_mut().is_none());
+    }
+
+    #[test]
+    fn mem_pool_is_send_sync() {
+        assert_send_sync::<MemPool<Vec<u8>>>();
+    }
+
+    #[test]
+    fn concurrent_pulls_from_shared_pool() {
+        let mem_pool = Arc::new(MemPool::new(4, 16,
+                                             || { Vec::<u8>::with_capacity(4096) }));
+
+        let threads: Vec<_> = (0..8).map(|_| {
+            let mem_pool = Arc::clone(&mem_pool);
+
+            std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    if let Some(mut mem) = mem_pool.try_pull() {
+                        mem.push(1);
+                    }
+                }
+            })
+        }).collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut pulled = Vec::new();
+
+        while let Some(mem) = mem_pool.try_pull() {
+            pulled.push(mem);
+        }
+
+        assert!(!pulled.is_empty());
+    }
+
+    #[test]
+    fn pull_methods_share_borrow() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let shared = &mem_pool;
+
+        let pooled = shared.try_pull();
+        let fallback = shared.try_pull_with_fallback(|| { Vec::<u8>::with_capacity(16) });
+
+        assert_eq!(pooled.unwrap().capacity(), 4096);
+        assert_eq!(fallback.capacity(), 16);
+    }
+
+    #[test]
+    fn len_tracks_pulled_buffers() {
+        let mem_pool = MemPool::new(2, 4,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        assert_eq!(mem_pool.len(), 8);
+
+        let first = mem_pool.try_pull().unwrap();
+        let second = mem_pool.try_pull().unwrap();
+
+        assert_eq!(mem_pool.len(), 6);
+        assert!(!mem_pool.is_empty());
+
+        drop(first);
+        drop(second);
+
+        assert_eq!(mem_pool.len(), 8);
+    }
+
+    #[test]
+    fn detach_then_attach() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let object = mem_pool.try_pull().unwrap().detach();
+
+        assert!(mem_pool.try_pull().is_none());
+
+        mem_pool.attach(object);
+
+        assert!(mem_pool.try_pull().is_some());
+
+        // The pool is full, so this buffer is dropped
+        mem_pool.attach(Vec::with_capacity(4096));
+
+        assert_eq!(mem_pool.len(), 1);
+    }
+
+    #[test]
+    fn capacity_accessors() {
+        let mem_pool = MemPool::new(8, 1000,
+                                    || { Vec::<u8>::with_capacity(16) });
+
+        assert_eq!(mem_pool.capacity(), 8000);
+        assert_eq!(mem_pool.bucket_count(), 8);
+        assert_eq!(mem_pool.capacity_per_bucket(), 1000);
+    }
+
+    #[test]
+    fn pull_blocks_until_attach() {
+        let mem_pool = Arc::new(MemPool::new(2, 1,
+                                             || { Vec::<u8>::with_capacity(4096) }));
+
+        let first = mem_pool.pull().detach();
+        let _second = mem_pool.pull().detach();
+
+        assert!(mem_pool.is_empty());
+
+        let thread = {
+            let mem_pool = Arc::clone(&mem_pool);
+
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+
+                mem_pool.attach(first);
+            })
+        };
+
+        let mem = mem_pool.pull();
+
+        assert_eq!(mem.capacity(), 4096);
+
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn pull_timeout_waits_for_attach() {
+        let mem_pool = Arc::new(MemPool::new(1, 1,
+                                             || { Vec::<u8>::with_capacity(4096) }));
+
+        let object = mem_pool.pull().detach();
+
+        assert!(mem_pool.pull_timeout(Duration::from_millis(10)).is_none());
+
+        let thread = {
+            let mem_pool = Arc::clone(&mem_pool);
+
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+
+                mem_pool.attach(object);
+            })
+        };
+
+        assert!(mem_pool.pull_timeout(Duration::from_millis(200)).is_some());
+
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn try_pull_scans_all_buckets() {
+        let mem_pool = MemPool::new(4, 1,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let objects: Vec<_> = (0..4).map(|_| mem_pool.try_pull().unwrap().detach()).collect();
+
+        assert!(mem_pool.try_pull().is_none());
+
+        mem_pool.inner.re_attach(3, objects.into_iter().next().unwrap());
+
+        for counter in 0..4 {
+            let mem = mem_pool.inner.try_pull_from_bucket(counter).unwrap();
+
+            assert_eq!(mem.bucket, 3);
+        }
+    }
+
+    #[test]
+    fn thread_affinity_keeps_threads_on_their_bucket() {
+        let mem_pool = MemPool::with_thread_affinity(4, 4,
+                                                     || { Vec::<u8>::with_capacity(4096) });
+
+        let first = mem_pool.try_pull().unwrap();
+        let second = mem_pool.try_pull().unwrap();
+
+        assert_eq!(first.bucket, second.bucket);
+        assert_eq!(first.bucket, crate::THREAD_INDEX.with(|index| *index) % 4);
+    }
+
+    #[test]
+    fn thread_affinity_steals_from_other_buckets() {
+        let mem_pool = MemPool::with_thread_affinity(4, 4,
+                                                     || { Vec::<u8>::with_capacity(4096) });
+
+        let home = std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let home = crate::THREAD_INDEX.with(|index| *index) % 4;
+
+                let local: Vec<_> = (0..4).map(|_| mem_pool.try_pull().unwrap()).collect();
+
+                assert!(local.iter().all(|mem| mem.bucket == home));
+
+                let stolen = mem_pool.try_pull().unwrap();
+
+                assert_eq!(stolen.bucket, home);
+                assert_eq!(mem_pool.bucket_len(home), 0);
+
+                drop(local);
+
+                home
+            }).join().unwrap()
+        });
+
+        // The stolen buffer overflowed into the following bucket, as the home bucket was full
+        assert_eq!(mem_pool.len(), 16);
+        assert_eq!(mem_pool.bucket_len(home), 4);
+    }
+
+    #[test]
+    fn clones_start_on_different_buckets() {
+        let mem_pool = MemPool::new(1024, 1,
+                                    || { Vec::<u8>::with_capacity(16) });
+
+        let first = mem_pool.clone();
+        let second = mem_pool.clone();
+
+        let first_bucket = first.try_pull().unwrap().bucket;
+        let second_bucket = second.try_pull().unwrap().bucket;
+
+        assert_ne!(first_bucket, second_bucket);
+    }
+
+    #[test]
+    fn round_robin_is_even_across_counter_wrap() {
+        // 2^64 isn't a multiple of 7, so a plain wrapping counter would skip buckets
+        let mut mem_pool = MemPool::new(7, 1,
+                                        || { Vec::<u8>::with_capacity(16) });
+
+        mem_pool.counter = AtomicUsize::new(COUNTER_REWIND_AT - 20);
+
+        let picked: Vec<usize> = (0..70)
+            .map(|_| mem_pool.inner.bucket_index(mem_pool.next_bucket()))
+            .collect();
+
+        assert!(picked.windows(2).all(|pair| pair[1] == (pair[0] + 1) % 7));
+
+        for bucket in 0..7 {
+            assert_eq!(picked.iter().filter(|picked| **picked == bucket).count(), 10);
+        }
+
+        assert!(mem_pool.counter.load(Ordering::Relaxed) < 100);
+
+        // Seeds past the rewind point keep their bucket
+        mem_pool.reseed_counter(usize::MAX);
+
+        assert!(mem_pool.counter.load(Ordering::Relaxed) < COUNTER_REWIND_AT);
+        assert_eq!(mem_pool.inner.bucket_index(mem_pool.next_bucket()), usize::MAX % 7);
+    }
+
+    #[test]
+    fn same_key_targets_same_bucket() {
+        let mem_pool = MemPool::new(8, 4,
+                                    || { Vec::<u8>::with_capacity(16) });
+
+        for key in ["alpha", "beta", "gamma"] {
+            let bucket = mem_pool.try_pull_for_key(key).unwrap().bucket;
+
+            for _ in 0..3 {
+                assert_eq!(mem_pool.try_pull_for_key(key).unwrap().bucket, bucket);
+            }
+
+            // Once the key's bucket is drained, other buckets aren't looked at
+            let held: Vec<_> = (0..4).map(|_| mem_pool.try_pull_for_key(key).unwrap()).collect();
+
+            assert!(held.iter().all(|mem| mem.bucket == bucket));
+            assert!(mem_pool.try_pull_for_key(key).is_none());
+        }
+    }
+
+    #[test]
+    fn try_pull_from_returns_to_same_bucket() {
+        let mem_pool = MemPool::new(4, 2,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let mem = mem_pool.try_pull_from(2).unwrap();
+
+        assert_eq!(mem_pool.bucket_len(2), 1);
+
+        drop(mem);
+
+        assert_eq!(mem_pool.bucket_len(2), 2);
+
+        let _first = mem_pool.try_pull_from(6).unwrap();
+        let _second = mem_pool.try_pull_from(2).unwrap();
+
+        assert!(mem_pool.try_pull_from(2).is_none());
+        assert_eq!(mem_pool.bucket_len(1), 2);
+    }
+
+    #[test]
+    fn bucket_lens_snapshot() {
+        let mem_pool = MemPool::new(3, 2,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let _first = mem_pool.try_pull_from(0).unwrap();
+        let _second = mem_pool.try_pull_from(2).unwrap();
+        let _third = mem_pool.try_pull_from(2).unwrap();
+
+        assert_eq!(mem_pool.bucket_lens(), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn clear_drops_pooled_buffers() {
+        let mem_pool = MemPool::new(2, 2,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let checked_out = mem_pool.try_pull().unwrap();
+
+        mem_pool.clear();
+
+        assert_eq!(mem_pool.len(), 0);
+        assert!(mem_pool.try_pull().is_none());
+
+        drop(checked_out);
+
+        assert!(mem_pool.try_pull().is_some());
+    }
+
+    #[test]
+    fn resize_each_shrinks_buckets() {
+        let mem_pool = MemPool::new(2, 4,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        mem_pool.resize_each(1, || { Vec::<u8>::with_capacity(4096) });
+
+        assert_eq!(mem_pool.bucket_lens(), vec![1, 1]);
+    }
+
+    #[test]
+    fn resize_each_grows_buckets() {
+        let mem_pool = MemPool::new(2, 4,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        mem_pool.clear();
+
+        mem_pool.resize_each(3, || { Vec::<u8>::with_capacity(4096) });
+
+        assert_eq!(mem_pool.bucket_lens(), vec![3, 3]);
+
+        mem_pool.resize_each(10, || { Vec::<u8>::with_capacity(4096) });
+
+        assert_eq!(mem_pool.bucket_lens(), vec![4, 4]);
+    }
+
+    #[test]
+    fn parallel_init_matches_serial() {
+        let serial = MemPool::new(8, 100,
+                                  || { Vec::<u8>::with_capacity(4096) });
+        let parallel = MemPool::new_parallel(8, 100,
+                                             || { Vec::<u8>::with_capacity(4096) });
+
+        assert_eq!(parallel.len(), serial.len());
+        assert_eq!(parallel.bucket_lens(), serial.bucket_lens());
+    }
+
+    #[test]
+    fn default_constructor() {
+        #[derive(Default, Debug, PartialEq)]
+        struct Connection {
+            id: u32,
+            open: bool,
+        }
+
+        let mem_pool = MemPool::<Connection>::new_default(2, 2);
+
+        assert_eq!(mem_pool.len(), 4);
+        assert_eq!(*mem_pool.try_pull().unwrap(), Connection::default());
+    }
+
+    #[test]
+    fn indexed_constructor() {
+        let mem_pool = MemPool::new_indexed(3, 4,
+                                            |bucket, slot| { bucket * 4 + slot });
+
+        let mut indexes = Vec::new();
+
+        while let Some(mem) = mem_pool.try_pull() {
+            indexes.push(mem.detach());
+        }
+
+        indexes.sort();
+
+        assert_eq!(indexes, (0..12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reset_hook_clears_returned_buffers() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { Vec::<u8>::with_capacity(4096) })
+            .with_reset(|mem| mem.clear());
+
+        {
+            let mut mem = mem_pool.try_pull().unwrap();
+
+            mem.extend_from_slice(&[1, 2, 3]);
+        }
+
+        let mem = mem_pool.try_pull().unwrap();
+
+        assert!(mem.is_empty());
+        assert_eq!(mem.capacity(), 4096);
+    }
+
+    #[test]
+    fn try_pull_clean_clears_buffer() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        mem_pool.try_pull().unwrap().extend_from_slice(&[1, 2, 3]);
+
+        let mem = mem_pool.try_pull_clean().unwrap();
+
+        assert!(mem.is_empty());
+    }
+
+    #[test]
+    fn debug_formatting() {
+        let mem_pool = MemPool::new(3, 2,
+                                    || { vec![1u8, 2] });
+
+        let formatted = format!("{:?}", mem_pool);
+
+        assert!(formatted.contains("buckets: 3"));
+        assert!(formatted.contains("len: 6"));
+
+        let mem = mem_pool.try_pull().unwrap();
+
+        assert_eq!(format!("{:?}", mem), "[1, 2]");
+        assert_eq!(format!("{:?}", mem.freeze().unwrap()), "[1, 2]");
+    }
+
+    #[test]
+    fn detach_with_pool_then_attach() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let (pool, object) = mem_pool.try_pull().unwrap().detach_with_pool();
+
+        assert!(mem_pool.try_pull().is_none());
+
+        pool.attach(object);
+
+        assert!(mem_pool.try_pull().is_some());
+    }
+
+    #[test]
+    fn detach_with_pool_keeps_handle_settings() {
+        let mem_pool = MemPool::with_thread_affinity(4, 1,
+                                                     || { Vec::<u8>::with_capacity(4096) })
+            .with_spin_budget(16);
+
+        let (pool, object) = mem_pool.try_pull().unwrap().detach_with_pool();
+
+        assert_eq!(pool.selection, crate::BucketSelection::ThreadAffinity);
+        assert_eq!(pool.spin_budget, 16);
+
+        pool.attach(object);
+
+        assert_eq!(mem_pool.len(), 4);
+    }
+
+    #[test]
+    fn owned_handle_moves_across_threads() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let mut mem = mem_pool.try_pull_owned().unwrap();
+
+        let length = std::thread::spawn(move || {
+            mem.extend_from_slice(&[1, 2, 3]);
+
+            mem.len()
+        }).join().unwrap();
+
+        assert_eq!(length, 3);
+        assert_eq!(mem_pool.len(), 1);
+    }
+
+    #[test]
+    fn from_buffers_drops_overflow() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+
+        struct Tagged(Arc<AtomicUsize>);
+
+        impl Drop for Tagged {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let buffers = (0..10).map(|_| Tagged(Arc::clone(&dropped)));
+
+        let mem_pool = MemPool::from_buffers(2, 4, buffers);
+
+        assert_eq!(mem_pool.len(), 8);
+        assert_eq!(mem_pool.bucket_lens(), vec![4, 4]);
+        assert_eq!(dropped.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn re_attach_overflows_into_other_buckets() {
+        let mem_pool = MemPool::new(2, 2,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let mem = mem_pool.try_pull_from(1).unwrap();
+        let _other = mem_pool.try_pull_from(0).unwrap();
+
+        // Bucket 1 is at capacity again when the handle is dropped
+        mem_pool.inner.re_attach(1, Vec::with_capacity(4096));
+
+        drop(mem);
+
+        assert_eq!(mem_pool.bucket_lens(), vec![2, 2]);
+    }
+
+    #[test]
+    fn metrics_track_pulls() {
+        let mem_pool = MemPool::new(1, 2,
+                                    || { Vec::<u8>::with_capacity(4096) })
+            .with_metrics();
+
+        let first = mem_pool.try_pull().unwrap();
+        let second = mem_pool.try_pull().unwrap();
+
+        assert!(mem_pool.try_pull().is_none());
+
+        let fallback = mem_pool.try_pull_with_fallback(|| { Vec::<u8>::with_capacity(4096) });
+
+        drop(first);
+        drop(second);
+        drop(fallback);
+
+        let metrics = mem_pool.metrics().unwrap();
+
+        assert_eq!(metrics, PoolMetricsSnapshot {
+            pulls: 4,
+            hits: 2,
+            misses: 1,
+            fallbacks: 1,
+            reattach_overflow_drops: 1,
+            ..metrics
+        });
+
+        assert!(MemPool::new(1, 1, || { 0u8 }).metrics().is_none());
+    }
+
+    #[cfg(all(feature = "cpu-affinity", target_os = "linux"))]
+    #[test]
+    fn cpu_affinity_is_stable_on_pinned_thread() {
+        let mem_pool = MemPool::new(4, 4,
+                                    || { Vec::<u8>::with_capacity(4096) })
+            .with_cpu_affinity();
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                let cpu = crate::current_cpu().unwrap();
+
+                // SAFETY: the cpu set is zero initialized before use and only refers to the current thread
+                unsafe {
+                    let mut set: libc::cpu_set_t = std::mem::zeroed();
+
+                    libc::CPU_SET(cpu, &mut set);
+
+                    assert_eq!(libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set), 0);
+                }
+
+                for _ in 0..10 {
+                    assert_eq!(mem_pool.try_pull().unwrap().bucket, cpu % 4);
+                }
+            });
+        });
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize_wipes_returned_buffers() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { vec![0u8; 64].into_boxed_slice() })
+            .with_zeroize();
+
+        mem_pool.try_pull().unwrap().fill(0xAB);
+
+        assert!(mem_pool.try_pull().unwrap().iter().all(|byte| *byte == 0));
+
+        let mem_pool = MemPool::new(1, 1,
+                                    || { Vec::<u8>::with_capacity(64) })
+            .with_zeroize();
+
+        mem_pool.try_pull().unwrap().extend_from_slice(&[0xAB; 64]);
+
+        assert!(mem_pool.try_pull().unwrap().is_empty());
+    }
+
+    #[test]
+    fn try_pull_many_is_all_or_nothing() {
+        let mem_pool = MemPool::new(2, 2,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        assert!(mem_pool.try_pull_many(5).is_none());
+        assert_eq!(mem_pool.len(), 4);
+
+        let pulled = mem_pool.try_pull_many(3).unwrap();
+
+        assert_eq!(pulled.len(), 3);
+        assert_eq!(mem_pool.len(), 1);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn pull_async_waits_for_returned_buffer() {
+        let mem_pool = Arc::new(MemPool::new(2, 1,
+                                             || { Vec::<u8>::with_capacity(4096) }));
+
+        let first = mem_pool.try_pull().unwrap().detach();
+        let _second = mem_pool.try_pull().unwrap().detach();
+
+        let waiter = {
+            let mem_pool = Arc::clone(&mem_pool);
+
+            tokio::spawn(async move {
+                mem_pool.pull_async().await.capacity()
+            })
+        };
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert!(!waiter.is_finished());
+
+        mem_pool.attach(first);
+
+        assert_eq!(waiter.await.unwrap(), 4096);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn pending_pull_async_does_not_spin() {
+        let mem_pool = Arc::new(MemPool::new(2, 2,
+                                             || { Vec::<u8>::with_capacity(4096) })
+            .with_metrics());
+
+        let held: Vec<_> = (0..4).map(|_| mem_pool.try_pull().unwrap().detach()).collect();
+
+        // Returns and pulls while nothing is waiting must not leave wakeups behind
+        for mem in held {
+            mem_pool.attach(mem);
+        }
+
+        let held: Vec<_> = (0..4).map(|_| mem_pool.try_pull().unwrap().detach()).collect();
+
+        let waiter = {
+            let mem_pool = Arc::clone(&mem_pool);
+
+            tokio::spawn(async move {
+                mem_pool.pull_async().await.capacity()
+            })
+        };
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert!(!waiter.is_finished());
+
+        mem_pool.attach_many(held);
+
+        assert_eq!(waiter.await.unwrap(), 4096);
+
+        let metrics = mem_pool.metrics().unwrap();
+
+        assert_eq!(metrics.misses, 0);
+        assert_eq!(metrics.hits, 9);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn pull_async_waits_for_outstanding_limit() {
+        let mem_pool = Arc::new(MemPool::new(2, 4,
+                                             || { Vec::<u8>::with_capacity(4096) })
+            .with_max_outstanding(1));
+
+        let held = mem_pool.try_pull().unwrap().freeze_mut();
+
+        let waiter = {
+            let mem_pool = Arc::clone(&mem_pool);
+
+            tokio::spawn(async move {
+                mem_pool.pull_async().await.capacity()
+            })
+        };
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        assert!(!waiter.is_finished());
+
+        // Detaching frees the slot without returning anything to the buckets
+        held.detach();
+
+        assert_eq!(mem_pool.len(), 7);
+        assert_eq!(waiter.await.unwrap(), 4096);
+    }
+
+    #[test]
+    fn freeze_then_thaw_keeps_bucket() {
+        let mem_pool = MemPool::new(4, 1,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let frozen = mem_pool.try_pull_from(2).unwrap().freeze().unwrap();
+
+        assert_eq!(frozen.bucket(), 2);
+
+        let mut thawed = frozen.thaw().unwrap();
+
+        thawed.push(1);
+
+        drop(thawed);
+
+        assert_eq!(mem_pool.bucket_lens(), vec![1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn shared_frozen_mem_returns_once() {
+        let mem_pool = MemPool::new(1, 2,
+                                    || { Vec::<u8>::with_capacity(4096) })
+            .with_metrics();
+
+        let frozen = mem_pool.try_pull().unwrap().freeze().unwrap();
+        let first = frozen.clone();
+        let second = frozen.clone();
+
+        let thread = std::thread::spawn(move || first.capacity());
+
+        assert_eq!(thread.join().unwrap(), 4096);
+
+        let frozen = frozen.thaw().unwrap_err();
+
+        drop(frozen);
+        assert_eq!(mem_pool.len(), 1);
+
+        drop(second);
+        assert_eq!(mem_pool.len(), 2);
+
+        assert_eq!(mem_pool.metrics().unwrap().reattach_overflow_drops, 0);
+    }
+
+    #[test]
+    fn in_use_and_peak() {
+        let mem_pool = MemPool::new(2, 2,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let first = mem_pool.try_pull().unwrap();
+        let second = mem_pool.try_pull().unwrap().freeze().unwrap();
+        let third = mem_pool.try_pull_owned().unwrap();
+
+        drop(first);
+
+        assert_eq!(mem_pool.in_use(), 2);
+        assert_eq!(mem_pool.peak_in_use(), 3);
+
+        drop(second);
+        let _detached = third.detach();
+
+        assert_eq!(mem_pool.in_use(), 0);
+        assert_eq!(mem_pool.peak_in_use(), 3);
+    }
+
+    #[test]
+    fn drain_takes_every_pooled_buffer() {
+        let mem_pool = MemPool::new(3, 4,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let checked_out = mem_pool.try_pull().unwrap();
+
+        let drained: Vec<_> = mem_pool.drain().collect();
+
+        assert_eq!(drained.len(), 11);
+        assert_eq!(mem_pool.len(), 0);
+
+        drop(checked_out);
+
+        assert_eq!(mem_pool.len(), 1);
+    }
+
+    #[test]
+    fn heterogeneous_capacities() {
+        let mem_pool = MemPool::new_with_capacities(&[1, 3],
+                                                    || { Vec::<u8>::with_capacity(4096) });
+
+        assert_eq!(mem_pool.capacity(), 4);
+        assert_eq!(mem_pool.bucket_lens(), vec![1, 3]);
+        assert_eq!(mem_pool.bucket_capacity(0), 1);
+        assert_eq!(mem_pool.capacity_per_bucket(), 3);
+
+        let buffers: Vec<_> = (0..3).map(|_| mem_pool.try_pull_from(1).unwrap().detach()).collect();
+
+        // Bucket 0 is full, so everything overflows into bucket 1
+        for mem in buffers {
+            mem_pool.inner.re_attach(0, mem);
+        }
+
+        assert_eq!(mem_pool.bucket_lens(), vec![1, 3]);
+    }
+
+    #[test]
+    fn try_pull_tagged_surfaces_bucket() {
+        let mem_pool = MemPool::new(4, 1,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let _first = mem_pool.try_pull_from(0).unwrap();
+
+        let (bucket, mem) = mem_pool.try_pull_tagged().unwrap();
+
+        assert_eq!(bucket, mem.bucket);
+        assert_ne!(bucket, 0);
+    }
+
+    #[test]
+    fn borrow_through_handle() {
+        use std::borrow::{Borrow, BorrowMut};
+        use std::collections::HashSet;
+
+        fn lookup<K: Borrow<String>>(set: &HashSet<&str>, key: K) -> bool {
+            let key: &str = key.borrow().borrow();
+
+            set.contains(key)
+        }
+
+        let mem_pool = MemPool::new(1, 1,
+                                    || { String::with_capacity(64) });
+
+        let mut mem = mem_pool.try_pull().unwrap();
+
+        BorrowMut::<String>::borrow_mut(&mut mem).push_str("pooled");
+
+        let set: HashSet<&str> = ["pooled"].into_iter().collect();
+
+        assert!(lookup(&set, mem));
+    }
+
+    #[test]
+    fn fallback_receives_bucket() {
+        let mem_pool = MemPool::new(4, 0,
+                                    || { 0usize });
+
+        for pull in 0..8 {
+            let mem = mem_pool.try_pull_with_fallback_indexed(|bucket| bucket);
+
+            assert_eq!(*mem, pull % 4);
+            assert_eq!(*mem, mem.bucket);
+        }
+
+        let mem_pool = MemPool::new(4, 1,
+                                    || { usize::MAX });
+
+        assert_eq!(*mem_pool.try_pull_with_fallback_indexed(|bucket| bucket), usize::MAX);
+    }
+
+    #[test]
+    fn pull_or_else_reports_fallbacks() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { Vec::<u8>::with_capacity(16) });
+
+        let (pooled, created) = mem_pool.pull_or_else(|| { Vec::with_capacity(32) });
+
+        assert!(!created);
+        assert_eq!(pooled.capacity(), 16);
+
+        let (fallback, created) = mem_pool.pull_or_else(|| { Vec::with_capacity(32) });
+
+        assert!(created);
+        assert_eq!(fallback.capacity(), 32);
+        assert_eq!(mem_pool.created_total(), 2);
+    }
+
+    #[test]
+    fn reseed_sets_next_bucket() {
+        let mem_pool = MemPool::new(4, 2,
+                                    || { Vec::<u8>::with_capacity(16) });
+
+        mem_pool.reseed_counter(6);
+
+        assert_eq!(mem_pool.try_pull().unwrap().bucket, 2);
+        assert_eq!(mem_pool.try_pull().unwrap().bucket, 3);
+
+        mem_pool.reseed_counter(0);
+
+        assert_eq!(mem_pool.try_pull().unwrap().bucket, 0);
+
+        mem_pool.reseed_counter_random();
+
+        assert!(mem_pool.try_pull().is_some());
+    }
+
+    #[test]
+    fn shared_handles_are_returned_on_drop() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { vec![1u8, 2, 3] });
+
+        let shared = mem_pool.try_pull_shared().unwrap();
+
+        let mem: &Vec<u8> = &shared;
+
+        assert_eq!(mem, &vec![1, 2, 3]);
+        assert!(mem_pool.try_pull_shared().is_none());
+
+        drop(shared);
+
+        assert_eq!(mem_pool.len(), 1);
+        assert_eq!(mem_pool.in_use(), 0);
+    }
+
+    #[test]
+    fn non_panicking_accessors() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let mut mem = mem_pool.try_pull().unwrap();
+
+        mem.get_mut().unwrap().push(1);
+
+        assert_eq!(mem.get(), Some(&vec![1]));
+
+        mem.mem = None;
+
+        assert!(mem.get().is_none());
+        assert!(mem.get_mut().is_none());
+    }
+
+    #[test]
+    fn consumer_panic_does_not_break_pool() {
+        let mem_pool = Arc::new(MemPool::new(1, 1,
+                                             || { Vec::<u8>::with_capacity(4096) }));
+
+        let result = {
+            let mem_pool = Arc::clone(&mem_pool);
+
+            std::thread::spawn(move || {
+                let _mem = mem_pool.try_pull().unwrap();
+
+                panic!("consumer failed while holding a buffer");
+            }).join()
+        };
+
+        assert!(result.is_err());
+
+        // The handle returned the buffer while unwinding
+        assert_eq!(mem_pool.len(), 1);
+        assert!(mem_pool.try_pull().is_some());
+    }
+
+    #[test]
+    fn auto_grow_keeps_extra_buffers() {
+        let mem_pool = MemPool::new(1, 2,
+                                    || { Vec::<u8>::with_capacity(4096) })
+            .with_auto_grow(8);
+
+        for _ in 0..3 {
+            let pulled: Vec<_> = (0..10)
+                .map(|_| mem_pool.try_pull_with_fallback(|| { Vec::<u8>::with_capacity(4096) }))
+                .collect();
+
+            drop(pulled);
+        }
+
+        assert_eq!(mem_pool.len(), 8);
+        assert_eq!(mem_pool.capacity(), 8);
+
+        let fixed = MemPool::new(1, 2,
+                                 || { Vec::<u8>::with_capacity(4096) });
+
+        let pulled: Vec<_> = (0..10)
+            .map(|_| fixed.try_pull_with_fallback(|| { Vec::<u8>::with_capacity(4096) }))
+            .collect();
+
+        drop(pulled);
+
+        assert_eq!(fixed.len(), 2);
+    }
+
+    #[test]
+    fn trim_drops_excess_buffers() {
+        let mem_pool = MemPool::new_with_capacities(&[4, 1, 3],
+                                                    || { Vec::<u8>::with_capacity(4096) });
+
+        mem_pool.trim(2);
+
+        assert_eq!(mem_pool.bucket_lens(), vec![2, 1, 2]);
+        assert_eq!(mem_pool.len(), 5);
+    }
+
+    #[test]
+    fn exhaustion_hook_fires_on_edge() {
+        let exhaustions = Arc::new(AtomicUsize::new(0));
+
+        let mem_pool = {
+            let exhaustions = Arc::clone(&exhaustions);
+
+            MemPool::new(1, 1,
+                         || { Vec::<u8>::with_capacity(4096) })
+                .with_exhaustion_hook(move || { exhaustions.fetch_add(1, Ordering::Relaxed); })
+        };
+
+        let mem = mem_pool.try_pull().unwrap();
+
+        assert_eq!(exhaustions.load(Ordering::Relaxed), 0);
+
+        assert!(mem_pool.try_pull().is_none());
+        assert!(mem_pool.try_pull().is_none());
+
+        assert_eq!(exhaustions.load(Ordering::Relaxed), 1);
+
+        drop(mem);
+
+        let _mem = mem_pool.try_pull().unwrap();
+
+        assert!(mem_pool.try_pull().is_none());
+
+        assert_eq!(exhaustions.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn exhaustion_hook_ignores_partial_misses() {
+        let exhaustions = Arc::new(AtomicUsize::new(0));
+
+        let mem_pool = {
+            let exhaustions = Arc::clone(&exhaustions);
+
+            MemPool::new(2, 2,
+                         || { Vec::<u8>::with_capacity(4096) })
+                .with_max_outstanding(2)
+                .with_exhaustion_hook(move || { exhaustions.fetch_add(1, Ordering::Relaxed); })
+        };
+
+        let held: Vec<_> = (0..2).map(|_| mem_pool.try_pull_from(0).unwrap()).collect();
+
+        // Only bucket 0 is empty
+        assert!(mem_pool.try_pull_from(0).is_none());
+        assert!(mem_pool.pull_from_blocking(0, Some(Duration::from_millis(1))).is_none());
+        assert!(mem_pool.try_pull_filtered(|mem| mem.capacity() == 0).is_none());
+
+        // Refused by the outstanding limit, while the buckets still hold buffers
+        assert!(mem_pool.try_pull().is_none());
+
+        assert_eq!(exhaustions.load(Ordering::Relaxed), 0);
+
+        drop(held);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn config_round_trip() {
+        let mem_pool = MemPool::new(4, 16,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let json = serde_json::to_string(&mem_pool.config()).unwrap();
+
+        let config = serde_json::from_str(&json).unwrap();
+
+        let rebuilt = MemPool::from_config(config,
+                                           || { Vec::<u8>::with_capacity(4096) });
+
+        assert_eq!(rebuilt.bucket_count(), 4);
+        assert_eq!(rebuilt.capacity_per_bucket(), 16);
+        assert_eq!(rebuilt.config(), mem_pool.config());
+    }
+
+    #[test]
+    fn with_buffer_returns_buffer() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { Vec::<u8>::with_capacity(4096) });
+
+        let length = mem_pool.with_buffer(|mem| {
+            mem.extend_from_slice(&[1, 2, 3]);
+
+            mem.len()
+        });
+
+        assert_eq!(length, Some(3));
+        assert_eq!(mem_pool.len(), 1);
+
+        let _mem = mem_pool.try_pull().unwrap();
+
+        assert!(mem_pool.with_buffer(|mem| mem.len()).is_none());
+    }
+
+    #[test]
+    fn validator_rejects_buffers() {
+        let mem_pool = MemPool::new(1, 2,
+                                    || { Vec::<u8>::with_capacity(16) })
+            .with_validator(|mem| mem.capacity() <= 64);
+
+        let mut grown = mem_pool.try_pull().unwrap();
+        let kept = mem_pool.try_pull().unwrap();
+
+        grown.extend_from_slice(&[0; 128]);
+
+        drop(grown);
+        drop(kept);
+
+        assert_eq!(mem_pool.len(), 1);
+        assert_eq!(mem_pool.try_pull().unwrap().capacity(), 16);
+    }
+
+    #[test]
+    fn spin_budget_pull() {
+        let mem_pool = MemPool::new(1, 1,
+                                    || { Vec::<u8>::with_capacity(4096) })
+            .with_spin_budget(1000);
+
+        std::thread::scope(|scope| {
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    for _ in 0..1000 {
+                        let mut mem = mem_pool.pull();
+
+                        mem.push(1);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(mem_pool.len(), 1);
+        assert!(mem_pool.pull_timeout(Duration::from_millis(1)).is_some());
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn leak_detection_reports_detached_buffers() {
+        let mem_pool = MemPool::new(1, 2,
+                                    || { Vec::<u8>::with_capacity(4096) })
+            .with_leak_detection();
+
+        drop(mem_pool.try_pull().unwrap().freeze().unwrap());
+
+        mem_pool.assert_no_leaks();
+
+        let detached = mem_pool.try_pull().unwrap().detach();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| mem_pool.assert_no_leaks()));
+
+        assert!(result.unwrap_err().downcast_ref::<String>().unwrap()
+            .contains("1 pooled buffers were never returned"));
+
+        mem_pool.attach(detached);
+
+        mem_pool.assert_no_leaks();
+    }
+
+    #[test]
+    fn pull_filtered_finds_matching_buffer() {
+        let mem_pool = MemPool::new_indexed(2, 2, |bucket, slot| bucket * 2 + slot);
+
+        let mem = mem_pool.try_pull_filtered(|tag| *tag == 3).unwrap();
+
+        assert_eq!(*mem, 3);
+        assert_eq!(mem_pool.len(), 3);
+
+        let mut remaining: Vec<usize> = mem_pool.drain().collect();
+
+        remaining.sort_unstable();
+
+        assert_eq!(remaining, vec![0, 1, 2]);
+
+        assert!(mem_pool.try_pull_filtered(|tag| *tag == 3).is_none());
+    }
+
+    #[test]
+    fn pull_filtered_keeps_skipped_buffers_in_place() {
+        let mem_pool = MemPool::new_indexed(2, 2, |bucket, slot| bucket * 2 + slot)
+            .with_balanced_selection();
+
+        let _held = mem_pool.try_pull_from(0).unwrap();
+
+        // Balanced returns would move the skipped buffers to the emptier bucket 0
+        let found = mem_pool.try_pull_filtered(|tag| *tag == 3).unwrap();
+
+        assert_eq!(*found, 3);
+        assert_eq!(mem_pool.bucket_lens(), vec![1, 1]);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            mem_pool.try_pull_filtered(|_| panic!("predicate panicked"))
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(mem_pool.bucket_lens(), vec![1, 1]);
+        assert_eq!(mem_pool.in_use(), 2);
+    }
+
+    #[test]
+    fn pow2_pools_mask_the_bucket_index() {
+        let mem_pool = MemPool::new_pow2(3, 2, || { Vec::<u8>::with_capacity(4096) });
+
+        assert_eq!(mem_pool.bucket_count(), 4);
+        assert_eq!(mem_pool.inner.mask, Some(3));
+
+        let buckets: Vec<usize> = (0..4).map(|bucket| mem_pool.try_pull_from(bucket + 4).unwrap().bucket).collect();
+
+        assert_eq!(buckets, vec![0, 1, 2, 3]);
+
+        let mem_pool = MemPool::new(3, 2, || { Vec::<u8>::with_capacity(4096) });
+
+        assert_eq!(mem_pool.inner.mask, None);
+        assert_eq!(mem_pool.try_pull_from(4).unwrap().bucket, 1);
+    }
+
+    #[test]
+    fn created_total_counts_fallback_allocations() {
+        let mem_pool = MemPool::new(1, 5, || { Vec::<u8>::with_capacity(4096) });
+
+        let held: Vec<_> = (0..8)
+            .map(|_| mem_pool.try_pull_with_fallback(|| { Vec::<u8>::with_capacity(4096) }))
+            .collect();
+
+        drop(held);
+
+        mem_pool.clear();
+
+        assert_eq!(mem_pool.created_total(), 8);
+    }
+
+    #[test]
+    fn pull_with_capacity_grows_the_buffer() {
+        let mem_pool = MemPool::new(1, 1, || { Vec::<u8>::with_capacity(4096) });
+
+        mem_pool.try_pull().unwrap().extend_from_slice(&[1, 2, 3]);
+
+        let mem = mem_pool.try_pull_with_capacity(10_000).unwrap();
+
+        assert!(mem.capacity() >= 10_000);
+        assert_eq!(mem.len(), 0);
+    }
+
+    #[test]
+    fn inspect_bucket_shows_stored_buffers() {
+        let mem_pool = MemPool::new(2, 2, || { Vec::<u8>::new() });
+
+        for tag in [7, 9] {
+            mem_pool.try_pull_from(1).unwrap().push(tag);
+        }
+
+        let tags = mem_pool.inspect_bucket(1, |mems| {
+            mems.iter().filter_map(|mem| mem.first().copied()).collect::<Vec<_>>()
+        });
+
+        assert_eq!(tags, vec![7, 9]);
+        assert_eq!(mem_pool.bucket_len(1), 2);
+        assert_eq!(mem_pool.inspect_bucket(0, |mems| mems.len()), 2);
+    }
+
+    #[test]
+    fn inspect_bucket_puts_buffers_back_in_place() {
+        let mem_pool = MemPool::new(2, 2, || { Vec::<u8>::new() })
+            .with_balanced_selection();
+
+        let _held = mem_pool.try_pull_from(0).unwrap();
+
+        // Balanced returns would move the buffers to the emptier bucket 0
+        assert_eq!(mem_pool.inspect_bucket(1, |mems| mems.len()), 2);
+        assert_eq!(mem_pool.bucket_lens(), vec![1, 2]);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            mem_pool.inspect_bucket(1, |mems| assert!(mems.is_empty()))
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(mem_pool.bucket_lens(), vec![1, 2]);
+    }
+
+    #[cfg(feature = "prefault")]
+    #[test]
+    fn prefault_keeps_buffers_in_place() {
+        let mem_pool = MemPool::new(2, 2, || { Vec::<u8>::with_capacity(1024 * 1024) });
+
+        mem_pool.prefault();
+
+        assert_eq!(mem_pool.bucket_lens(), vec![2, 2]);
+        assert!(mem_pool.drain().all(|mem| mem.is_empty() && mem.capacity() >= 1024 * 1024));
+    }
+
+    #[test]
+    fn freeze_flavors() {
+        let mem_pool = MemPool::new(1, 2, || { Vec::<u8>::with_capacity(16) });
+
+        let shared = mem_pool.try_pull().unwrap().freeze().unwrap();
+        let shared_clone = shared.clone();
+
+        assert_eq!(shared.bucket(), shared_clone.bucket());
+
+        let mut exclusive = mem_pool.try_pull().unwrap().freeze_mut();
+
+        exclusive.push(1);
+
+        std::thread::spawn(move || assert_eq!(*exclusive, vec![1])).join().unwrap();
+
+        assert_eq!(mem_pool.len(), 1);
+    }
+
+    #[test]
+    fn byte_pool_reuses_allocations() {
+        let mem_pool = MemPool::new_byte_pool(1, 1, 512);
+
+        let mut mem = mem_pool.try_pull().unwrap();
+
+        assert_eq!(mem.len(), 512);
+
+        mem[0] = 42;
+
+        let address = mem.as_ptr();
+
+        drop(mem);
+
+        let mem = mem_pool.try_pull().unwrap();
+
+        assert_eq!(mem.as_ptr(), address);
+        assert_eq!(mem[0], 42);
+    }
+
+    #[test]
+    fn attach_many_respects_capacity() {
+        let mem_pool = MemPool::new(2, 2, || { Vec::<u8>::with_capacity(16) })
+            .with_metrics();
+
+        mem_pool.clear();
+
+        mem_pool.attach_many((0..100).map(|_| Vec::<u8>::with_capacity(16)));
+
+        assert_eq!(mem_pool.bucket_lens(), vec![2, 2]);
+        assert_eq!(mem_pool.metrics().unwrap().reattach_overflow_drops, 96);
+    }
+
+    #[test]
+    fn weak_mem_does_not_keep_the_buffer() {
+        let mem_pool = MemPool::new(1, 1, || { Vec::<u8>::with_capacity(16) });
+
+        let shared = mem_pool.try_pull().unwrap().freeze().unwrap();
+        let weak = shared.downgrade();
+
+        assert!(weak.upgrade().is_some());
+
+        drop(shared);
+
+        assert!(weak.upgrade().is_none());
+        assert_eq!(mem_pool.len(), 1);
+    }
+
+    #[test]
+    fn max_outstanding_limits_checked_out_buffers() {
+        let mem_pool = MemPool::new(2, 4, || { Vec::<u8>::with_capacity(16) })
+            .with_max_outstanding(2);
+
+        let first = mem_pool.try_pull().unwrap();
+        let _second = mem_pool.try_pull().unwrap();
+
+        assert!(mem_pool.try_pull().is_none());
+        assert!(mem_pool.pull_timeout(Duration::from_millis(10)).is_none());
+
+        drop(first);
+
+        assert!(mem_pool.try_pull().is_some());
+
+        std::thread::scope(|scope| {
+            let third = mem_pool.try_pull().unwrap();
+
+            scope.spawn(move || {
+                std::thread::sleep(Duration::from_millis(20));
+
+                drop(third);
+            });
+
+            let _fourth = mem_pool.pull();
+
+            assert_eq!(mem_pool.in_use(), 2);
+        });
+    }
+
+    #[test]
+    fn balanced_selection_keeps_buckets_even() {
+        let mem_pool = MemPool::new(4, 4, || { Vec::<u8>::with_capacity(16) })
+            .with_balanced_selection();
+
+        let assert_balanced = || {
+            let lens = mem_pool.bucket_lens();
+
+            assert!(lens.iter().max().unwrap() - lens.iter().min().unwrap() <= 1, "{:?}", lens);
+        };
+
+        let mut held = Vec::new();
+
+        for round in 0..20 {
+            for _ in 0..(round % 5) {
+                held.push(mem_pool.try_pull().unwrap());
+
+                assert_balanced();
+            }
+
+            for _ in 0..(round % 4) {
+                held.pop();
+
+                assert_balanced();
+            }
+        }
+    }
+
+    #[test]
+    fn rebalance_moves_buffers_to_new_shape() {
+        let mem_pool = MemPool::new(2, 3, || { Vec::<u8>::with_capacity(16) });
+
+        let held = mem_pool.try_pull_owned().unwrap();
+
+        // 5 buffers are stored, only 4 fit in the new pool
+        let rebalanced = mem_pool.rebalance(4, 1);
+
+        assert_eq!(rebalanced.bucket_count(), 4);
+        assert_eq!(rebalanced.bucket_lens(), vec![1, 1, 1, 1]);
+
+        // The checked out buffer goes back to the old pool
+        drop(held);
+
+        assert_eq!(rebalanced.len(), 4);
+    }
+
+    #[test]
+    fn tags_are_per_checkout() {
+        let mem_pool = MemPool::new(1, 1, || { Vec::<u8>::with_capacity(16) });
+
+        let mut mem = mem_pool.try_pull().unwrap();
+
+        assert_eq!(mem.tag(), None);
+
+        mem.set_tag(42);
+
+        assert_eq!(mem.tag(), Some(42));
+
+        drop(mem);
+
+        assert_eq!(mem_pool.try_pull().unwrap().tag(), None);
+    }
+
+    #[test]
+    fn buckets_hand_out_least_recently_used_first() {
+        let mem_pool = MemPool::new(1, 3, || { Vec::<u8>::new() });
+
+        let held: Vec<_> = (0..3).map(|_| mem_pool.try_pull().unwrap()).collect();
+
+        for (tag, mut mem) in (1..=3).zip(held) {
+            mem.push(tag);
+        }
+
+        let order: Vec<u8> = (0..3).map(|_| mem_pool.try_pull().unwrap().detach()[0]).collect();
+
+        assert_eq!(order, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn snapshot_matches_pool_state() {
+        let mem_pool = MemPool::new(2, 2, || { Vec::<u8>::with_capacity(16) });
+
+        let _held = mem_pool.try_pull_from(1).unwrap();
+        let _fallback = mem_pool.try_pull_with_fallback(|| { Vec::<u8>::with_capacity(16) });
+
+        assert_eq!(mem_pool.snapshot(), PoolSnapshot {
+            bucket_lens: vec![1, 1],
+            in_use: 2,
+            created_total: 4,
+            capacity: 4,
+        });
+    }
+
+    #[test]
+    fn try_from_vec_creates_single_bucket() {
+        let mem_pool = MemPool::try_from(vec![1, 2, 3]).unwrap();
+
+        assert_eq!(mem_pool.bucket_count(), 1);
+
+        let held: Vec<_> = (0..3).map(|_| mem_pool.try_pull().unwrap()).collect();
+
+        assert_eq!(held.iter().map(|mem| **mem).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(mem_pool.try_pull().is_none());
+
+        assert!(MemPool::<u8>::try_from(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn reroute_returns_to_another_pool() {
+        let source = MemPool::new(1, 2, || { Vec::<u8>::with_capacity(16) });
+        let dest = MemPool::new(1, 2, || { Vec::<u8>::with_capacity(16) });
+
+        dest.clear();
+
+        source.try_pull().unwrap().reroute(&dest);
+
+        assert_eq!(source.len(), 1);
+        assert_eq!(source.in_use(), 0);
+        assert_eq!(dest.len(), 1);
+    }
+
+    #[test]
+    fn reroute_reports_the_return_to_both_pools() {
+        let source_events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let dest_events = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let source = {
+            let sink = Arc::clone(&source_events);
+
+            MemPool::new(1, 2, || { Vec::<u8>::with_capacity(16) })
+                .with_tracing(move |event| sink.lock().unwrap().push(event))
+        };
+
+        let dest = {
+            let sink = Arc::clone(&dest_events);
+
+            MemPool::new(2, 2, || { Vec::<u8>::with_capacity(16) })
+                .with_tracing(move |event| sink.lock().unwrap().push(event))
+                .with_reset(|mem: &mut Vec<u8>| mem.clear())
+        };
+
+        dest.clear();
+
+        let returned = Arc::new(AtomicUsize::new(0));
+        let returned_clone = Arc::clone(&returned);
+
+        let mut mem = source.try_pull().unwrap()
+            .on_return(move |mem| { returned_clone.store(mem.len(), Ordering::Relaxed) });
+
+        mem.extend_from_slice(&[1, 2]);
+
+        mem.reroute(&dest);
+
+        assert_eq!(returned.load(Ordering::Relaxed), 2);
+        assert_eq!(source.in_use(), 0);
+
+        assert!(matches!(source_events.lock().unwrap()[..],
+            [TraceEvent::Pull { bucket: 0, .. }, TraceEvent::Return { bucket: 0, .. }]));
+        assert!(matches!(dest_events.lock().unwrap()[..], [TraceEvent::Reroute { .. }]));
+
+        assert!(dest.try_pull().unwrap().is_empty());
+    }
+
+    #[test]
+    fn refill_brings_buckets_to_capacity() {
+        let mem_pool = MemPool::new_with_capacities(&[1, 3], || { Vec::<u8>::with_capacity(16) });
+
+        let _held = mem_pool.try_pull().unwrap();
+
+        mem_pool.clear();
+
+        mem_pool.refill(|| { Vec::<u8>::with_capacity(16) });
+
+        assert_eq!(mem_pool.bucket_lens(), vec![1, 3]);
+        assert_eq!(mem_pool.len(), mem_pool.capacity());
+        assert_eq!(mem_pool.created_total(), 8);
+    }
+
+    #[test]
+    fn on_return_runs_when_the_handle_is_dropped() {
+        let mem_pool = MemPool::new(1, 1, || { Vec::<u8>::with_capacity(16) });
+
+        let returned = Arc::new(AtomicUsize::new(0));
+        let returned_clone = Arc::clone(&returned);
+
+        let mut mem = mem_pool.try_pull().unwrap()
+            .on_return(move |mem| { returned_clone.store(mem.len(), Ordering::Relaxed) });
+
+        mem.extend_from_slice(&[1, 2, 3]);
+
+        drop(mem);
+
+        assert_eq!(returned.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn metrics_record_hold_time() {
+        let mem_pool = MemPool::new(1, 1, || { Vec::<u8>::with_capacity(16) })
+            .with_metrics();
+
+        assert_eq!(mem_pool.metrics().unwrap().mean_hold_time(), None);
+
+        let mem = mem_pool.try_pull().unwrap();
+
+        std::thread::sleep(Duration::from_millis(50));
+
+        drop(mem);
+
+        let mean = mem_pool.metrics().unwrap().mean_hold_time().unwrap();
+
+        assert!(mean >= Duration::from_millis(50) && mean < Duration::from_secs(5), "{:?}", mean);
+    }
+
+    #[test]
+    fn inner_pool_low_level_api() {
+        let inner = InnerPool::new(2, 2, || { Vec::<u8>::with_capacity(16) });
+
+        assert_eq!(inner.bucket_count(), 2);
+        assert_eq!(inner.capacity(), 4);
+
+        let first = inner.try_pull(1).unwrap();
+        let second = inner.try_pull_exact(1).unwrap();
+
+        assert_eq!((first.bucket, second.bucket), (1, 1));
+        assert!(inner.try_pull_exact(1).is_none());
+        assert_eq!(inner.try_pull(1).unwrap().bucket, 0);
+
+        inner.attach(1, second.detach());
+
+        assert_eq!(inner.bucket_len(1), 1);
+
+        drop(first);
+
+        assert_eq!(inner.len(), 4);
+    }
+
+    #[test]
+    fn ptr_eq_detects_clones() {
+        let mem_pool = MemPool::new(1, 1, || { Vec::<u8>::with_capacity(16) });
+        let other = MemPool::new(1, 1, || { Vec::<u8>::with_capacity(16) });
+
+        assert!(mem_pool.ptr_eq(&mem_pool.clone()));
+        assert!(!mem_pool.ptr_eq(&other));
+    }
+
+    #[test]
+    fn tracing_reports_pulls_and_returns() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = Arc::clone(&events);
+
+        let mem_pool = MemPool::new(2, 2, || { Vec::<u8>::with_capacity(16) })
+            .with_tracing(move |event| sink.lock().unwrap().push(event));
+
+        for _ in 0..3 {
+            let first = mem_pool.try_pull().unwrap();
+
+            drop(mem_pool.try_pull().unwrap());
+            drop(first);
+        }
+
+        let events = events.lock().unwrap();
+
+        let mut outstanding = vec![0isize; 2];
+
+        for event in events.iter() {
+            match event {
+                TraceEvent::Pull { bucket, .. } => outstanding[*bucket] += 1,
+                TraceEvent::Return { bucket, .. } => outstanding[*bucket] -= 1,
+                TraceEvent::Reroute { .. } => unreachable!(),
+            }
+
+            assert!(outstanding.iter().all(|count| *count >= 0));
+        }
+
+        assert_eq!(events.len(), 12);
+        assert_eq!(outstanding, vec![0, 0]);
+    }
+
+    #[test]
+    fn freeze_returns_the_shared_buffer() {
+        let mem_pool = MemPool::new(1, 1, || { vec![1u8, 2] });
+
+        let shared = mem_pool.try_pull().unwrap().freeze();
+
+        assert_eq!(shared.as_deref(), Some(&vec![1, 2]));
+    }
+
+    #[test]
+    fn concurrent_pulls_never_lose_or_duplicate_buffers() {
+        let mem_pool = MemPool::new_indexed(4, 8, |bucket, slot| bucket * 8 + slot);
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..10_000 {
+                        let first = mem_pool.try_pull();
+                        let second = mem_pool.try_pull();
+
+                        drop(first);
+                        drop(second);
+                    }
+                });
+            }
+        });
+
+        let mut ids: Vec<usize> = mem_pool.drain().collect();
+
+        ids.sort_unstable();
+
+        assert_eq!(ids, (0..32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn into_buffers_needs_every_buffer_back() {
+        let mem_pool = MemPool::new(2, 2, || { Vec::<u8>::with_capacity(16) });
+
+        let held = mem_pool.try_pull_owned().unwrap();
+
+        let mem_pool = mem_pool.into_buffers().unwrap_err();
+
+        drop(held);
+
+        assert_eq!(mem_pool.into_buffers().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn try_new_stops_at_the_first_error() {
+        struct Tracked(Arc<AtomicUsize>);
+
+        impl Drop for Tracked {
+            fn drop(&mut self) {
+                self.0.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+
+        let live = Arc::new(AtomicUsize::new(0));
+        let calls = AtomicUsize::new(0);
+
+        let result = MemPool::try_new(2, 4, || {
+            if calls.fetch_add(1, Ordering::Relaxed) == 4 {
+                return Err("init failed");
+            }
+
+            live.fetch_add(1, Ordering::Relaxed);
+
+            Ok(Tracked(Arc::clone(&live)))
+        });
+
+        assert_eq!(result.unwrap_err(), "init failed");
+        assert_eq!(calls.load(Ordering::Relaxed), 5);
+        assert_eq!(live.load(Ordering::Relaxed), 0);
+
+        let mem_pool = MemPool::try_new(2, 4, || Ok::<_, ()>(0u8)).unwrap();
+
+        assert_eq!(mem_pool.len(), 8);
+    }
+
+    #[test]
+    fn pull_from_blocking_waits_for_its_bucket() {
+        let mem_pool = MemPool::new(2, 1, || { Vec::<u8>::with_capacity(16) });
+
+        let held = mem_pool.try_pull_from(1).unwrap().detach();
+
+        assert!(mem_pool.pull_from_blocking(1, Some(Duration::from_millis(10))).is_none());
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+
+                mem_pool.inner.attach(1, held);
+            });
+
+            let mem = mem_pool.pull_from_blocking(1, None).unwrap();
+
+            assert_eq!(mem.bucket, 1);
+        });
+
+        assert_eq!(mem_pool.bucket_lens(), vec![1, 1]);
+    }
+}
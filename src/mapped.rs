@@ -0,0 +1,200 @@
+use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, DerefMut};
+use crate::{MemPool, MutMemShare, PooledMem};
+
+type MapFn<T, U> = Box<dyn Fn(T) -> U + Send + Sync>;
+
+/// A [MemPool] whose buffers are converted to another type while checked out, see
+/// [MemPool::map]
+pub struct MappedPool<T, U> {
+    pool: MemPool<T>,
+    to: MapFn<T, U>,
+    from: MapFn<U, T>,
+}
+
+impl<T, U> MappedPool<T, U> {
+    pub(crate) fn new<F, R>(pool: MemPool<T>, to: F, from: R) -> Self
+        where F: Fn(T) -> U + Send + Sync + 'static, R: Fn(U) -> T + Send + Sync + 'static {
+        Self {
+            pool,
+            to: Box::new(to),
+            from: Box::new(from),
+        }
+    }
+
+    /// Same as [MemPool::try_pull], converting the buffer on the way out
+    pub fn try_pull(&self) -> Option<MappedMem<'_, T, U>> {
+        let mut checkout = MappedCheckout {
+            handle: self.pool.try_pull()?,
+            detached: false,
+        };
+
+        let mem = checkout.handle.mem.take().map(&self.to);
+
+        Some(MappedMem {
+            pool: self,
+            checkout,
+            mem,
+        })
+    }
+
+    /// The pool holding the unconverted buffers
+    pub fn pool(&self) -> &MemPool<T> {
+        &self.pool
+    }
+}
+
+impl<T, U> Debug for MappedPool<T, U> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MappedPool")
+            .field("pool", &self.pool)
+            .finish()
+    }
+}
+
+/// Keeps the checkout of a buffer, without holding it while it's converted. If a
+/// conversion panics the buffer is lost, but it's still checked back in.
+struct MappedCheckout<'a, T> {
+    handle: MutMemShare<'a, T>,
+    /// The buffer left the pool for good, see [PooledMem::detach]
+    detached: bool,
+}
+
+impl<'a, T> Drop for MappedCheckout<'a, T> {
+    fn drop(&mut self) {
+        // The handle is dropped right after this, returning the buffer if it's back
+        if !self.detached && self.handle.mem.is_none() {
+            self.handle.pool.check_in(&mut self.handle.checkout);
+        }
+    }
+}
+
+/// A buffer pulled from a [MappedPool], converted back when it is returned
+pub struct MappedMem<'a, T, U> {
+    pool: &'a MappedPool<T, U>,
+    checkout: MappedCheckout<'a, T>,
+    mem: Option<U>,
+}
+
+impl<'a, T, U> PooledMem<U> for MappedMem<'a, T, U> {
+    fn detach(mut self) -> U {
+        self.checkout.handle.pool.detach_checkout(&mut self.checkout.handle.checkout);
+
+        self.checkout.detached = true;
+
+        if let Some(mem) = self.mem.take() {
+            mem
+        } else {
+            unreachable!()
+        }
+    }
+}
+
+impl<'a, T, U> Deref for MappedMem<'a, T, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.mem {
+            Some(mem) => { mem }
+            None => { unreachable!() }
+        }
+    }
+}
+
+impl<'a, T, U> DerefMut for MappedMem<'a, T, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match &mut self.mem {
+            Some(mem) => { mem }
+            None => { unreachable!() }
+        }
+    }
+}
+
+impl<'a, T, U> Debug for MappedMem<'a, T, U> where U: Debug {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T, U> Drop for MappedMem<'a, T, U> {
+    fn drop(&mut self) {
+        // The checkout is dropped right after this, returning the converted buffer
+        if let Some(mem) = self.mem.take() {
+            self.checkout.handle.mem = Some((self.pool.from)(mem));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use crate::{MemPool, PooledMem};
+
+    struct Frame(Vec<u8>);
+
+    #[test]
+    fn mapped_buffers_keep_their_allocation() {
+        let mapped = MemPool::new(1, 1, || { Vec::<u8>::with_capacity(64) })
+            .map(Frame, |frame| frame.0);
+
+        let mut frame = mapped.try_pull().unwrap();
+
+        frame.0.push(1);
+
+        let address = frame.0.as_ptr();
+
+        drop(frame);
+
+        let frame = mapped.try_pull().unwrap();
+
+        assert_eq!(frame.0.as_ptr(), address);
+        assert_eq!(frame.0, vec![1]);
+        assert_eq!(mapped.pool().in_use(), 1);
+
+        frame.detach();
+
+        assert_eq!(mapped.pool().in_use(), 0);
+        assert!(mapped.try_pull().is_none());
+    }
+
+    #[test]
+    fn panicking_conversions_check_the_buffer_in() {
+        let panic_to = Arc::new(AtomicBool::new(true));
+        let panic_from = Arc::new(AtomicBool::new(false));
+
+        let mapped = {
+            let (panic_to, panic_from) = (Arc::clone(&panic_to), Arc::clone(&panic_from));
+
+            MemPool::new(1, 2, || { Vec::<u8>::with_capacity(64) })
+                .map(move |mem| {
+                    assert!(!panic_to.load(Ordering::Relaxed), "to panicked");
+
+                    Frame(mem)
+                }, move |frame| {
+                    assert!(!panic_from.load(Ordering::Relaxed), "from panicked");
+
+                    frame.0
+                })
+        };
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| mapped.try_pull()));
+
+        assert!(result.is_err());
+        assert_eq!(mapped.pool().in_use(), 0);
+        assert_eq!(mapped.pool().len(), 1);
+
+        panic_to.store(false, Ordering::Relaxed);
+        panic_from.store(true, Ordering::Relaxed);
+
+        let frame = mapped.try_pull().unwrap();
+
+        assert_eq!(mapped.pool().in_use(), 1);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| drop(frame)));
+
+        assert!(result.is_err());
+        assert_eq!(mapped.pool().in_use(), 0);
+        assert!(mapped.pool().is_empty());
+    }
+}
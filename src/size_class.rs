@@ -0,0 +1,162 @@
+use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use crate::{InnerPool, PooledMem};
+
+/// A pool of byte buffers split into size classes (e.g. 4K, 16K, 64K), with one
+/// bucket per class. Pulls ask for a minimum size and get a buffer of the smallest
+/// class that fits, and buffers are returned to the class matching their capacity.
+pub struct SizeClassPool {
+    inner: Arc<InnerPool<Vec<u8>>>,
+    /// The size of each class, in ascending order. Class `i` is stored in bucket `i`.
+    classes: Vec<usize>,
+}
+
+impl SizeClassPool {
+    /// Create a pool with the given size classes, each holding `capacity_per_class` buffers
+    pub fn new(classes: &[usize], capacity_per_class: usize) -> Self {
+        let mut classes = classes.to_vec();
+
+        classes.sort_unstable();
+        classes.dedup();
+
+        let inner = InnerPool::new_indexed(classes.len(), capacity_per_class,
+                                           |class, _| Vec::with_capacity(classes[class]));
+
+        Self {
+            inner,
+            classes,
+        }
+    }
+
+    /// The sizes of the classes of this pool, in ascending order
+    pub fn classes(&self) -> &[usize] {
+        &self.classes
+    }
+
+    /// Pull a buffer with a capacity of at least `bytes`, from the smallest class that
+    /// fits. Larger classes are tried when that one is empty.
+    pub fn try_pull_class(&self, bytes: usize) -> Option<SizeClassMem<'_>> {
+        let first = self.classes.partition_point(|class| *class < bytes);
+
+        (first..self.classes.len())
+            .find_map(|class| self.inner.buckets_rxs[class].try_recv().ok())
+            .map(|mem| SizeClassMem {
+                pool: self,
+                mem: Some(mem),
+            })
+    }
+
+    /// The amount of buffers currently stored in each class
+    pub fn class_lens(&self) -> Vec<usize> {
+        self.inner.buckets_rxs.iter().map(|rx| rx.len()).collect()
+    }
+
+    /// Return a buffer to the largest class it can serve. Buffers smaller than every
+    /// class, or whose class is full, are dropped.
+    fn re_attach(&self, mem: Vec<u8>) {
+        let class = self.classes.partition_point(|class| *class <= mem.capacity());
+
+        if class > 0 {
+            let _ = self.inner.try_push(class - 1, mem);
+        }
+    }
+}
+
+impl Debug for SizeClassPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SizeClassPool")
+            .field("classes", &self.classes)
+            .field("class_lens", &self.class_lens())
+            .finish()
+    }
+}
+
+/// A buffer pulled from a [SizeClassPool]
+pub struct SizeClassMem<'a> {
+    pool: &'a SizeClassPool,
+    mem: Option<Vec<u8>>,
+}
+
+impl<'a> PooledMem<Vec<u8>> for SizeClassMem<'a> {
+    fn detach(mut self) -> Vec<u8> {
+        if let Some(mem) = self.mem.take() {
+            mem
+        } else {
+            unreachable!()
+        }
+    }
+}
+
+impl<'a> Deref for SizeClassMem<'a> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.mem {
+            Some(mem) => { mem }
+            None => { unreachable!() }
+        }
+    }
+}
+
+impl<'a> DerefMut for SizeClassMem<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match &mut self.mem {
+            Some(mem) => { mem }
+            None => { unreachable!() }
+        }
+    }
+}
+
+impl<'a> Debug for SizeClassMem<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a> Drop for SizeClassMem<'a> {
+    fn drop(&mut self) {
+        if let Some(mem) = self.mem.take() {
+            self.pool.re_attach(mem);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SizeClassPool;
+
+    const KB: usize = 1024;
+
+    #[test]
+    fn pull_picks_smallest_fitting_class() {
+        let pool = SizeClassPool::new(&[64 * KB, 4 * KB, 16 * KB], 2);
+
+        assert_eq!(pool.classes(), &[4 * KB, 16 * KB, 64 * KB]);
+
+        let mem = pool.try_pull_class(5 * KB).unwrap();
+
+        assert!(mem.capacity() >= 16 * KB);
+        assert!(mem.capacity() < 64 * KB);
+        assert_eq!(pool.class_lens(), vec![2, 1, 2]);
+
+        assert!(pool.try_pull_class(128 * KB).is_none());
+    }
+
+    #[test]
+    fn returned_buffers_are_routed_by_capacity() {
+        let pool = SizeClassPool::new(&[4 * KB, 16 * KB, 64 * KB], 2);
+
+        let _first = pool.try_pull_class(64 * KB).unwrap();
+
+        let mut mem = pool.try_pull_class(KB).unwrap();
+
+        mem.reserve_exact(64 * KB);
+
+        assert_eq!(pool.class_lens(), vec![1, 2, 1]);
+
+        drop(mem);
+
+        assert_eq!(pool.class_lens(), vec![1, 2, 2]);
+    }
+}
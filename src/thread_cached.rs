@@ -0,0 +1,433 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::fmt::{Debug, Formatter};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::{MemPool, OwnedMemShare, PooledMem};
+
+/// Gives each [ThreadCachedPool] its own stash in every thread
+static POOL_IDS: AtomicU64 = AtomicU64::new(0);
+
+/// A thread's stash for one pool: the pool id, a token that dies with the pool and
+/// the stash itself
+type StashEntry = (u64, Weak<()>, Arc<dyn Any + Send + Sync>);
+
+thread_local! {
+    /// The stash of each [ThreadCachedPool] used by this thread. Threads rarely use
+    /// more than a couple of pools, so a linear search beats hashing the id.
+    static STASHES: RefCell<Vec<StashEntry>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The buffers stashed by one thread for one pool. The pool keeps a weak reference to
+/// it, so it can take the buffers back when it's dropped.
+struct Stash<T> {
+    mems: Mutex<Vec<OwnedMemShare<T>>>,
+}
+
+impl<T> Drop for Stash<T> {
+    fn drop(&mut self) {
+        // The thread exited without flushing its stash
+        for mem in self.mems.get_mut().unwrap().drain(..) {
+            release_recycled(mem);
+        }
+    }
+}
+
+/// Give a stashed buffer back to the shared pool. It was recycled on its way into the
+/// stash, so this skips the reset hook and validator that dropping it would run again.
+fn release_recycled<T>(mut mem: OwnedMemShare<T>) {
+    if let Some(inner) = mem.mem.take() {
+        mem.pool.returned(mem.bucket, &mut mem.checkout);
+
+        mem.pool.store(mem.bucket, inner);
+    }
+}
+
+/// A two level pool: each thread keeps a few buffers in a local stash and only goes to
+/// the shared [MemPool] when its stash is empty (on pulls) or full (on returns), so
+/// the common case never touches the buckets.
+///
+/// Stashed buffers stay checked out of the shared pool: they count towards its
+/// outstanding limit and [MemPool::in_use], and only show up in its length once they
+/// go back to the buckets. The reset hook and validator of the pool run once per
+/// return, whether the buffer is kept in the stash or overflows into the shared pool,
+/// and aren't run again when stashed buffers go back to the shared pool. That happens
+/// on [ThreadCachedPool::flush], when the thread exits or when this pool is dropped.
+pub struct ThreadCachedPool<T> {
+    pool: MemPool<T>,
+    id: u64,
+    stash_size: usize,
+    /// The stash of every thread that used this pool
+    stashes: Mutex<Vec<Weak<Stash<T>>>>,
+    /// Lets threads forget their stash once this pool is dropped
+    alive: Arc<()>,
+}
+
+impl<T> ThreadCachedPool<T> where T: Send + 'static {
+    /// Back each thread's stash of up to `stash_size` buffers with `pool`
+    pub fn new(pool: MemPool<T>, stash_size: usize) -> Self {
+        Self {
+            pool,
+            id: POOL_IDS.fetch_add(1, Ordering::Relaxed),
+            stash_size,
+            stashes: Mutex::new(Vec::new()),
+            alive: Arc::new(()),
+        }
+    }
+
+    /// Pull a buffer from the stash of the current thread, or the shared pool when it
+    /// is empty
+    pub fn try_pull(&self) -> Option<ThreadCachedMem<'_, T>> {
+        let mem = match self.with_stash(|stash| stash.pop()).flatten() {
+            Some(mem) => mem,
+            None => self.pool.try_pull()?.freeze_mut(),
+        };
+
+        Some(ThreadCachedMem {
+            pool: self,
+            mem: Some(mem),
+        })
+    }
+
+    /// Move the stash of the current thread back to the shared pool
+    pub fn flush(&self) {
+        for mem in self.with_stash(std::mem::take).unwrap_or_default() {
+            release_recycled(mem);
+        }
+    }
+
+    /// The shared pool backing the stashes
+    pub fn pool(&self) -> &MemPool<T> {
+        &self.pool
+    }
+
+    pub fn stash_size(&self) -> usize {
+        self.stash_size
+    }
+
+    /// The amount of buffers in the stash of the current thread
+    pub fn stash_len(&self) -> usize {
+        self.with_stash(|stash| stash.len()).unwrap_or(0)
+    }
+
+    /// Run `f` on the stash of the current thread. Returns `None` when the thread is
+    /// already being torn down.
+    fn with_stash<R, F>(&self, f: F) -> Option<R> where F: FnOnce(&mut Vec<OwnedMemShare<T>>) -> R {
+        STASHES.try_with(|stashes| {
+            let mut stashes = stashes.borrow_mut();
+
+            let index = match stashes.iter().position(|(id, _, _)| *id == self.id) {
+                Some(index) => index,
+                None => {
+                    // Forget the stashes of the pools that were dropped, which emptied them
+                    stashes.retain(|(_, alive, _)| alive.strong_count() > 0);
+
+                    stashes.push((self.id, Arc::downgrade(&self.alive), self.register_stash()));
+
+                    stashes.len() - 1
+                }
+            };
+
+            match stashes[index].2.downcast_ref::<Stash<T>>() {
+                Some(stash) => f(&mut stash.mems.lock().unwrap()),
+                None => unreachable!()
+            }
+        }).ok()
+    }
+
+    fn register_stash(&self) -> Arc<Stash<T>> {
+        let stash = Arc::new(Stash {
+            mems: Mutex::new(Vec::with_capacity(self.stash_size)),
+        });
+
+        let mut stashes = self.stashes.lock().unwrap();
+
+        // The stashes of the threads that exited
+        stashes.retain(|stash| stash.strong_count() > 0);
+
+        stashes.push(Arc::downgrade(&stash));
+
+        stash
+    }
+
+    /// Keep a returned buffer in the stash of the current thread, or give it back to
+    /// the shared pool when the stash is full
+    fn re_attach(&self, mut mem: OwnedMemShare<T>) {
+        let recycled = match mem.mem.as_mut() {
+            Some(inner) => mem.pool.recycle(inner),
+            None => false,
+        };
+
+        // Rejected buffers are dropped, as the shared pool does, without validating
+        // them a second time
+        if !recycled {
+            if mem.mem.take().is_some() {
+                mem.pool.returned(mem.bucket, &mut mem.checkout);
+            }
+
+            return;
+        }
+
+        let mut mem = Some(mem);
+
+        self.with_stash(|stash| {
+            if stash.len() < self.stash_size {
+                stash.extend(mem.take());
+            }
+        });
+
+        // The buffers that don't fit (or find the thread being torn down) go straight
+        // back to the shared pool
+        if let Some(mem) = mem {
+            release_recycled(mem);
+        }
+    }
+}
+
+impl<T> Debug for ThreadCachedPool<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ThreadCachedPool")
+            .field("pool", &self.pool)
+            .field("stash_size", &self.stash_size)
+            .finish()
+    }
+}
+
+impl<T> Drop for ThreadCachedPool<T> {
+    fn drop(&mut self) {
+        // Nothing borrows the pool anymore, so the stashes are only touched by the
+        // threads that are exiting
+        for stash in self.stashes.get_mut().unwrap().drain(..).filter_map(|stash| stash.upgrade()) {
+            let mems = std::mem::take(&mut *stash.mems.lock().unwrap());
+
+            for mem in mems {
+                release_recycled(mem);
+            }
+        }
+    }
+}
+
+/// A buffer pulled from a [ThreadCachedPool]. It stays checked out of the shared pool
+/// while it's in a stash.
+pub struct ThreadCachedMem<'a, T> where T: Send + 'static {
+    pool: &'a ThreadCachedPool<T>,
+    mem: Option<OwnedMemShare<T>>,
+}
+
+impl<'a, T> PooledMem<T> for ThreadCachedMem<'a, T> where T: Send + 'static {
+    fn detach(mut self) -> T {
+        if let Some(mem) = self.mem.take() {
+            mem.detach()
+        } else {
+            unreachable!()
+        }
+    }
+}
+
+impl<'a, T> Deref for ThreadCachedMem<'a, T> where T: Send + 'static {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        match &self.mem {
+            Some(mem) => { mem }
+            None => { unreachable!() }
+        }
+    }
+}
+
+impl<'a, T> DerefMut for ThreadCachedMem<'a, T> where T: Send + 'static {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match &mut self.mem {
+            Some(mem) => { mem }
+            None => { unreachable!() }
+        }
+    }
+}
+
+impl<'a, T> Debug for ThreadCachedMem<'a, T> where T: Send + Debug + 'static {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T> Drop for ThreadCachedMem<'a, T> where T: Send + 'static {
+    fn drop(&mut self) {
+        if let Some(mem) = self.mem.take() {
+            self.pool.re_attach(mem);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::{MemPool, ThreadCachedPool};
+
+    #[test]
+    fn stash_serves_pulls_first() {
+        let pool = ThreadCachedPool::new(MemPool::new(2, 4, || { Vec::<u8>::with_capacity(16) }), 2);
+
+        let held: Vec<_> = (0..3).map(|_| pool.try_pull().unwrap()).collect();
+
+        assert_eq!(pool.pool().len(), 5);
+
+        // Two returns fill the stash, the third one overflows into the shared pool
+        drop(held);
+
+        assert_eq!(pool.stash_len(), 2);
+        assert_eq!(pool.pool().len(), 6);
+        assert_eq!(pool.pool().in_use(), 2);
+
+        let _mem = pool.try_pull().unwrap();
+
+        assert_eq!(pool.stash_len(), 1);
+        assert_eq!(pool.pool().len(), 6);
+    }
+
+    #[test]
+    fn stashes_return_when_threads_exit() {
+        let pool = Arc::new(ThreadCachedPool::new(MemPool::new(4, 8, || { Vec::<u8>::with_capacity(16) }), 2));
+
+        let workers: Vec<_> = (0..8).map(|_| {
+            let pool = Arc::clone(&pool);
+
+            std::thread::spawn(move || {
+                for round in 0..1000 {
+                    let held: Vec<_> = (0..round % 4 + 1)
+                        .filter_map(|_| pool.try_pull())
+                        .collect();
+
+                    drop(held);
+                }
+            })
+        }).collect();
+
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        assert_eq!(pool.pool().len(), 32);
+        assert_eq!(pool.pool().in_use(), 0);
+    }
+
+    #[test]
+    fn dropping_the_pool_empties_every_stash() {
+        let shared = MemPool::new(1, 4, || { Vec::<u8>::with_capacity(16) });
+
+        let pool = Arc::new(ThreadCachedPool::new(shared.clone(), 2));
+
+        let (stashed_tx, stashed_rx) = std::sync::mpsc::channel();
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+
+        let worker = {
+            let pool = Arc::clone(&pool);
+
+            std::thread::spawn(move || {
+                drop(pool.try_pull());
+                drop(pool);
+
+                stashed_tx.send(()).unwrap();
+
+                // Stay alive, so the stash isn't returned by the thread exiting
+                let _ = done_rx.recv();
+            })
+        };
+
+        stashed_rx.recv().unwrap();
+
+        assert_eq!(shared.len(), 3);
+
+        drop(pool);
+
+        assert_eq!(shared.len(), 4);
+
+        drop(done_tx);
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn stashed_buffers_stay_checked_out() {
+        let pool = ThreadCachedPool::new(MemPool::new(2, 2, || { Vec::<u8>::with_capacity(16) })
+                                             .with_max_outstanding(1)
+                                             .with_reset(|mem: &mut Vec<u8>| mem.clear()), 2);
+
+        let mut mem = pool.try_pull().unwrap();
+
+        mem.push(1);
+
+        assert!(pool.try_pull().is_none());
+
+        drop(mem);
+
+        assert_eq!(pool.pool().in_use(), 1);
+
+        // Served from the stash, reset on the way in
+        assert!(pool.try_pull().unwrap().is_empty());
+
+        pool.flush();
+
+        assert_eq!(pool.pool().in_use(), 0);
+        assert_eq!(pool.pool().len(), 4);
+    }
+
+    #[test]
+    fn buffers_are_reset_once_per_return() {
+        let resets = Arc::new(AtomicUsize::new(0));
+        let validations = Arc::new(AtomicUsize::new(0));
+
+        let shared = {
+            let (resets, validations) = (Arc::clone(&resets), Arc::clone(&validations));
+
+            MemPool::new(1, 4, || { Vec::<u8>::with_capacity(16) })
+                .with_reset(move |mem: &mut Vec<u8>| {
+                    resets.fetch_add(1, Ordering::Relaxed);
+
+                    mem.clear()
+                })
+                .with_validator(move |mem: &Vec<u8>| {
+                    validations.fetch_add(1, Ordering::Relaxed);
+
+                    mem.capacity() >= 16
+                })
+        };
+
+        let pool = ThreadCachedPool::new(shared.clone(), 1);
+
+        // One return is stashed, the other two overflow into the shared pool
+        drop((0..3).map(|_| pool.try_pull().unwrap()).collect::<Vec<_>>());
+
+        assert_eq!(resets.load(Ordering::Relaxed), 3);
+        assert_eq!(pool.stash_len(), 1);
+
+        pool.flush();
+
+        assert_eq!(resets.load(Ordering::Relaxed), 3);
+        assert_eq!(shared.len(), 4);
+
+        drop(pool.try_pull().unwrap());
+
+        assert_eq!(resets.load(Ordering::Relaxed), 4);
+
+        // Rejected on the way into the stash, and not validated again by the shared pool
+        let mut rejected = pool.try_pull().unwrap();
+
+        rejected.shrink_to_fit();
+
+        drop(rejected);
+
+        assert_eq!(validations.load(Ordering::Relaxed), 5);
+        assert_eq!(pool.pool().in_use(), 0);
+
+        drop(pool.try_pull().unwrap());
+
+        drop(pool);
+
+        assert_eq!(resets.load(Ordering::Relaxed), 5);
+        assert_eq!(validations.load(Ordering::Relaxed), 6);
+        assert_eq!(shared.len(), 3);
+        assert_eq!(shared.in_use(), 0);
+    }
+}
@@ -0,0 +1,48 @@
+//! Runs with its own counting global allocator, which would otherwise be installed for
+//! every unit test of the crate
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use mem_pool::MemPool;
+
+/// Counts the allocations made by the current thread, so tests running in parallel
+/// don't affect each other
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn try_pull_does_not_allocate() {
+    let mem_pool = MemPool::new(4, 4, || { Vec::<u8>::with_capacity(4096) });
+
+    let before = ALLOCATIONS.with(|allocations| allocations.get());
+
+    for _ in 0..1000 {
+        let mut mem = mem_pool.try_pull().unwrap();
+
+        mem.push(1);
+    }
+
+    assert_eq!(ALLOCATIONS.with(|allocations| allocations.get()), before);
+
+    drop(std::hint::black_box(Vec::<u8>::with_capacity(16)));
+
+    assert_eq!(ALLOCATIONS.with(|allocations| allocations.get()), before + 1);
+}